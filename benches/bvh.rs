@@ -0,0 +1,56 @@
+use cgmath::Vector3;
+use criterion::{criterion_group, criterion_main, Criterion};
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+
+use rust_tracer::bvh::Bvh;
+use rust_tracer::camera::Camera;
+use rust_tracer::ray::Ray;
+use rust_tracer::sampler::{IndependentSampler, Sampler};
+use rust_tracer::scenes::random_world;
+use rust_tracer::shapes::{Hittable, World};
+
+fn camera_rays(count: usize) -> Vec<Ray> {
+    let camera = Camera::new(
+        Vector3::new(13.0, 2.0, 3.0),
+        Vector3::new(0.0, 0.0, 0.0),
+        Vector3::new(0.0, 1.0, 0.0),
+        20.0,
+        0.1,
+        10.0,
+        1200,
+        800,
+    );
+    let mut sampler = IndependentSampler::new(0);
+
+    (0..count)
+        .map(|_| {
+            let (s, t) = sampler.get_2d();
+            camera.get_ray(s, t, &mut sampler)
+        })
+        .collect()
+}
+
+fn closest_hits<T: Hittable>(scene: &T, rays: &[Ray]) -> usize {
+    rays.iter()
+        .filter(|ray| scene.hit(ray, 0.001, f64::INFINITY).is_some())
+        .count()
+}
+
+fn random_world_hits(c: &mut Criterion) {
+    let scene = random_world(&mut Pcg32::seed_from_u64(0)).build().unwrap();
+    let world = scene.world;
+    let flat = World {
+        objects: world.objects.clone(),
+    };
+    let bvh = Bvh::new(world.objects);
+    let rays = camera_rays(4096);
+
+    let mut group = c.benchmark_group("random_world");
+    group.bench_function("flat", |b| b.iter(|| closest_hits(&flat, &rays)));
+    group.bench_function("bvh", |b| b.iter(|| closest_hits(&bvh, &rays)));
+    group.finish();
+}
+
+criterion_group!(benches, random_world_hits);
+criterion_main!(benches);
@@ -0,0 +1,135 @@
+use cgmath::{Matrix4, Vector3};
+
+use crate::ray::Ray;
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aabb {
+    pub min: Vector3<f64>,
+    pub max: Vector3<f64>,
+}
+
+const PADDING: f64 = 1e-4;
+
+impl Aabb {
+    pub fn new(a: Vector3<f64>, b: Vector3<f64>) -> Self {
+        Aabb {
+            min: Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z)),
+            max: Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z)),
+        }
+    }
+
+    pub fn empty() -> Self {
+        Aabb {
+            min: Vector3::new(f64::INFINITY, f64::INFINITY, f64::INFINITY),
+            max: Vector3::new(f64::NEG_INFINITY, f64::NEG_INFINITY, f64::NEG_INFINITY),
+        }
+    }
+
+    pub fn from_points<I: IntoIterator<Item = Vector3<f64>>>(points: I) -> Self {
+        points
+            .into_iter()
+            .fold(Aabb::empty(), |bounds, p| bounds.grow(p))
+    }
+
+    // Flat primitives such as quads and triangles get a little thickness so
+    // that the slab test never has to deal with a zero-width interval.
+    pub fn padded(&self) -> Self {
+        let mut padded = *self;
+        for axis in 0..3 {
+            if padded.max[axis] - padded.min[axis] < PADDING {
+                padded.min[axis] -= PADDING / 2.0;
+                padded.max[axis] += PADDING / 2.0;
+            }
+        }
+        padded
+    }
+
+    pub fn union(&self, other: &Aabb) -> Self {
+        Aabb {
+            min: Vector3::new(
+                self.min.x.min(other.min.x),
+                self.min.y.min(other.min.y),
+                self.min.z.min(other.min.z),
+            ),
+            max: Vector3::new(
+                self.max.x.max(other.max.x),
+                self.max.y.max(other.max.y),
+                self.max.z.max(other.max.z),
+            ),
+        }
+    }
+
+    pub fn grow(&self, p: Vector3<f64>) -> Self {
+        self.union(&Aabb { min: p, max: p })
+    }
+
+    pub fn centroid(&self) -> Vector3<f64> {
+        0.5 * (self.min + self.max)
+    }
+
+    pub fn surface_area(&self) -> f64 {
+        let d = self.max - self.min;
+        if d.x < 0.0 || d.y < 0.0 || d.z < 0.0 {
+            0.0
+        } else {
+            2.0 * (d.x * d.y + d.y * d.z + d.z * d.x)
+        }
+    }
+
+    pub fn longest_axis(&self) -> usize {
+        let d = self.max - self.min;
+        if d.x > d.y && d.x > d.z {
+            0
+        } else if d.y > d.z {
+            1
+        } else {
+            2
+        }
+    }
+
+    pub fn corners(&self) -> [Vector3<f64>; 8] {
+        let (a, b) = (self.min, self.max);
+        [
+            Vector3::new(a.x, a.y, a.z),
+            Vector3::new(b.x, a.y, a.z),
+            Vector3::new(a.x, b.y, a.z),
+            Vector3::new(b.x, b.y, a.z),
+            Vector3::new(a.x, a.y, b.z),
+            Vector3::new(b.x, a.y, b.z),
+            Vector3::new(a.x, b.y, b.z),
+            Vector3::new(b.x, b.y, b.z),
+        ]
+    }
+
+    pub fn transform(&self, m: &Matrix4<f64>) -> Self {
+        Aabb::from_points(
+            self.corners()
+                .into_iter()
+                .map(|p| (m * p.extend(1.0)).truncate()),
+        )
+    }
+
+    pub fn hit(
+        &self,
+        ray: &Ray,
+        inv_direction: Vector3<f64>,
+        mut t_min: f64,
+        mut t_max: f64,
+    ) -> bool {
+        for axis in 0..3 {
+            let mut t0 = (self.min[axis] - ray.origin[axis]) * inv_direction[axis];
+            let mut t1 = (self.max[axis] - ray.origin[axis]) * inv_direction[axis];
+            if inv_direction[axis] < 0.0 {
+                std::mem::swap(&mut t0, &mut t1);
+            }
+
+            t_min = t0.max(t_min);
+            t_max = t1.min(t_max);
+            if t_max < t_min {
+                return false;
+            }
+        }
+
+        true
+    }
+}
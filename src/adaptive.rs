@@ -0,0 +1,94 @@
+use serde::{Deserialize, Serialize};
+
+// Stops sampling a pixel once it has had `min_samples` samples and the noise
+// left in it is below `threshold`; `samples_per_pixel` becomes the maximum.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct AdaptiveSampling {
+    pub min_samples: u32,
+    pub threshold: f64,
+}
+
+impl Default for AdaptiveSampling {
+    fn default() -> Self {
+        AdaptiveSampling {
+            min_samples: 16,
+            threshold: 0.005,
+        }
+    }
+}
+
+// Running mean and variance of a pixel's luminance (Welford's algorithm).
+#[derive(Debug, Default, Clone, Copy)]
+pub struct Welford {
+    count: u32,
+    mean: f64,
+    m2: f64,
+}
+
+impl Welford {
+    pub fn push(&mut self, value: f64) {
+        self.count += 1;
+        let delta = value - self.mean;
+        self.mean += delta / self.count as f64;
+        self.m2 += delta * (value - self.mean);
+    }
+
+    pub fn mean(&self) -> f64 {
+        self.mean
+    }
+
+    pub fn variance(&self) -> f64 {
+        if self.count < 2 {
+            0.0
+        } else {
+            self.m2 / (self.count - 1) as f64
+        }
+    }
+
+    // The standard error of the mean as it shows after the square root the
+    // display encoders apply, so that dark and bright pixels converge to
+    // about the same visible noise.
+    pub fn error(&self) -> f64 {
+        let standard_error = (self.variance() / self.count.max(1) as f64).sqrt();
+        standard_error / (2.0 * self.mean.max(1e-4).sqrt())
+    }
+}
+
+impl AdaptiveSampling {
+    pub fn converged(&self, statistics: &Welford) -> bool {
+        statistics.count >= self.min_samples && statistics.error() <= self.threshold
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn statistics(values: impl IntoIterator<Item = f64>) -> Welford {
+        let mut statistics = Welford::default();
+        for value in values {
+            statistics.push(value);
+        }
+        statistics
+    }
+
+    // A pixel without noise stops as soon as it may, and not before.
+    #[test]
+    fn pixels_take_the_minimum_samples_first() {
+        let adaptive = AdaptiveSampling::default();
+        let flat = |count| statistics(std::iter::repeat_n(0.5, count));
+
+        assert!(!adaptive.converged(&flat(0)));
+        assert!(!adaptive.converged(&flat(adaptive.min_samples as usize - 1)));
+        assert!(adaptive.converged(&flat(adaptive.min_samples as usize)));
+    }
+
+    #[test]
+    fn noisy_pixels_keep_sampling() {
+        let adaptive = AdaptiveSampling::default();
+        let noisy = statistics((0..64).map(|i| (i % 2) as f64));
+        assert!((noisy.mean() - 0.5).abs() < 1e-12);
+        assert!(!adaptive.converged(&noisy));
+    }
+}
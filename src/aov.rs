@@ -0,0 +1,127 @@
+use std::ops::{Add, Mul};
+
+use cgmath::InnerSpace;
+use serde::{Deserialize, Serialize};
+
+use crate::{colour::Colour, ray::Ray, shapes::HitRecord};
+
+// Arbitrary output variables rendered alongside the beauty image. The lighting
+// ones split the beauty image by how light reached the camera and sum back up
+// to it. Samples is a heatmap of how many samples each pixel took, filled in
+// by the render loop rather than by the integrators.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Aov {
+    Albedo,
+    Normal,
+    Depth,
+    DirectDiffuse,
+    IndirectDiffuse,
+    Specular,
+    Emission,
+    Alpha,
+    Samples,
+}
+
+impl Aov {
+    pub const ALL: [Aov; 9] = [
+        Aov::Albedo,
+        Aov::Normal,
+        Aov::Depth,
+        Aov::DirectDiffuse,
+        Aov::IndirectDiffuse,
+        Aov::Specular,
+        Aov::Emission,
+        Aov::Alpha,
+        Aov::Samples,
+    ];
+
+    pub fn name(self) -> &'static str {
+        match self {
+            Aov::Albedo => "albedo",
+            Aov::Normal => "normal",
+            Aov::Depth => "depth",
+            Aov::DirectDiffuse => "direct_diffuse",
+            Aov::IndirectDiffuse => "indirect_diffuse",
+            Aov::Specular => "specular",
+            Aov::Emission => "emission",
+            Aov::Alpha => "alpha",
+            Aov::Samples => "samples",
+        }
+    }
+
+    // Which lighting AOV light belongs to that was emitted or sampled after
+    // `bounces` bounces on a path whose first bounce was diffuse or not.
+    pub fn lighting(bounces: u32, diffuse: bool) -> Aov {
+        match (bounces, diffuse) {
+            (0, _) => Aov::Emission,
+            (_, false) => Aov::Specular,
+            (1, true) => Aov::DirectDiffuse,
+            (_, true) => Aov::IndirectDiffuse,
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+pub struct Aovs {
+    values: [Colour<f64>; Aov::ALL.len()],
+}
+
+impl Default for Aovs {
+    fn default() -> Self {
+        Aovs {
+            values: [Colour::new(0.0, 0.0, 0.0); Aov::ALL.len()],
+        }
+    }
+}
+
+impl Aovs {
+    pub fn get(&self, aov: Aov) -> Colour<f64> {
+        self.values[aov as usize]
+    }
+
+    pub fn set(&mut self, aov: Aov, value: Colour<f64>) {
+        self.values[aov as usize] = value;
+    }
+
+    pub fn add(&mut self, aov: Aov, value: Colour<f64>) {
+        self.values[aov as usize] = self.values[aov as usize] + value;
+    }
+
+    // Fills in the AOVs that only depend on what the camera ray hit. Depth is
+    // the distance along the ray, and zero like everything else on a miss.
+    pub fn record_first_hit(&mut self, ray: &Ray, hit_record: Option<&HitRecord>) {
+        let Some(hit_record) = hit_record else {
+            return;
+        };
+
+        let n = hit_record.normal;
+        let depth = hit_record.t * ray.direction.magnitude();
+        self.set(Aov::Albedo, hit_record.material.albedo(hit_record));
+        self.set(Aov::Normal, Colour::new(n.x, n.y, n.z));
+        self.set(Aov::Depth, Colour::new(depth, depth, depth));
+        self.set(Aov::Alpha, Colour::new(1.0, 1.0, 1.0));
+    }
+}
+
+impl Add for Aovs {
+    type Output = Self;
+
+    fn add(mut self, rhs: Self) -> Self::Output {
+        for (value, other) in self.values.iter_mut().zip(rhs.values) {
+            *value = *value + other;
+        }
+        self
+    }
+}
+
+impl Mul<f64> for Aovs {
+    type Output = Self;
+
+    fn mul(mut self, rhs: f64) -> Self::Output {
+        for value in self.values.iter_mut() {
+            *value = *value * rhs;
+        }
+        self
+    }
+}
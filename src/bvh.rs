@@ -0,0 +1,377 @@
+use cgmath::Vector3;
+
+use crate::{
+    aabb::Aabb,
+    ray::Ray,
+    shapes::{HitRecord, Hittable},
+};
+
+const BUCKET_COUNT: usize = 12;
+const MAX_PRIMITIVES_IN_LEAF: usize = 4;
+const TRAVERSAL_COST: f64 = 0.125;
+// Past this depth nodes are split at the median so that the traversal stack
+// can never overflow, however skewed the surface area heuristic splits get.
+const MAX_SAH_DEPTH: usize = 32;
+const STACK_SIZE: usize = 64;
+
+enum NodeKind {
+    Leaf { first: usize, count: usize },
+    Interior { second_child: usize, axis: usize },
+}
+
+struct Node {
+    bounds: Aabb,
+    kind: NodeKind,
+}
+
+struct BuildPrimitive {
+    index: usize,
+    bounds: Aabb,
+    centroid: Vector3<f64>,
+}
+
+pub struct Bvh<T> {
+    primitives: Vec<T>,
+    nodes: Vec<Node>,
+    unbounded: Vec<T>,
+}
+
+fn partition<T, F: Fn(&T) -> bool>(items: &mut [T], predicate: F) -> usize {
+    let mut split = 0;
+    for i in 0..items.len() {
+        if predicate(&items[i]) {
+            items.swap(i, split);
+            split += 1;
+        }
+    }
+    split
+}
+
+fn bucket_index(centroid_bounds: &Aabb, axis: usize, centroid: Vector3<f64>) -> usize {
+    let extent = centroid_bounds.max[axis] - centroid_bounds.min[axis];
+    let offset = (centroid[axis] - centroid_bounds.min[axis]) / extent;
+    ((offset * BUCKET_COUNT as f64) as usize).min(BUCKET_COUNT - 1)
+}
+
+// Picks the bucket boundary along the longest centroid axis that minimises
+// the surface area heuristic, or None if a leaf is cheaper.
+fn find_split(
+    primitives: &[BuildPrimitive],
+    bounds: &Aabb,
+    centroid_bounds: &Aabb,
+) -> Option<(usize, usize)> {
+    let axis = centroid_bounds.longest_axis();
+    if centroid_bounds.max[axis] <= centroid_bounds.min[axis] {
+        return None;
+    }
+
+    let mut counts = [0usize; BUCKET_COUNT];
+    let mut bucket_bounds = [Aabb::empty(); BUCKET_COUNT];
+    for primitive in primitives {
+        let b = bucket_index(centroid_bounds, axis, primitive.centroid);
+        counts[b] += 1;
+        bucket_bounds[b] = bucket_bounds[b].union(&primitive.bounds);
+    }
+
+    let (best_bucket, best_cost) = (0..BUCKET_COUNT - 1)
+        .map(|split| {
+            let (below, above) = (&bucket_bounds[..=split], &bucket_bounds[split + 1..]);
+            let below_area = below
+                .iter()
+                .fold(Aabb::empty(), |a, b| a.union(b))
+                .surface_area();
+            let above_area = above
+                .iter()
+                .fold(Aabb::empty(), |a, b| a.union(b))
+                .surface_area();
+            let below_count: usize = counts[..=split].iter().sum();
+            let above_count: usize = counts[split + 1..].iter().sum();
+
+            let cost = TRAVERSAL_COST
+                + (below_count as f64 * below_area + above_count as f64 * above_area)
+                    / bounds.surface_area();
+            (split, cost)
+        })
+        .min_by(|a, b| a.1.total_cmp(&b.1))?;
+
+    if primitives.len() > MAX_PRIMITIVES_IN_LEAF || best_cost < primitives.len() as f64 {
+        Some((axis, best_bucket))
+    } else {
+        None
+    }
+}
+
+fn build(
+    nodes: &mut Vec<Node>,
+    primitives: &mut [BuildPrimitive],
+    offset: usize,
+    depth: usize,
+) -> usize {
+    let bounds = primitives
+        .iter()
+        .fold(Aabb::empty(), |bounds, p| bounds.union(&p.bounds));
+    let centroid_bounds = Aabb::from_points(primitives.iter().map(|p| p.centroid));
+
+    let node_index = nodes.len();
+    let leaf = Node {
+        bounds,
+        kind: NodeKind::Leaf {
+            first: offset,
+            count: primitives.len(),
+        },
+    };
+
+    let (axis, mut mid) = if depth < MAX_SAH_DEPTH {
+        let Some((axis, bucket)) = find_split(primitives, &bounds, &centroid_bounds) else {
+            nodes.push(leaf);
+            return node_index;
+        };
+
+        let mid = partition(primitives, |p| {
+            bucket_index(&centroid_bounds, axis, p.centroid) <= bucket
+        });
+        (axis, mid)
+    } else if primitives.len() > MAX_PRIMITIVES_IN_LEAF {
+        (centroid_bounds.longest_axis(), 0)
+    } else {
+        nodes.push(leaf);
+        return node_index;
+    };
+
+    if mid == 0 || mid == primitives.len() {
+        mid = primitives.len() / 2;
+        primitives
+            .select_nth_unstable_by(mid, |a, b| a.centroid[axis].total_cmp(&b.centroid[axis]));
+    }
+
+    nodes.push(Node {
+        bounds,
+        kind: NodeKind::Interior {
+            second_child: 0,
+            axis,
+        },
+    });
+
+    let (below, above) = primitives.split_at_mut(mid);
+    build(nodes, below, offset, depth + 1);
+    let second = build(nodes, above, offset + mid, depth + 1);
+    nodes[node_index].kind = NodeKind::Interior {
+        second_child: second,
+        axis,
+    };
+
+    node_index
+}
+
+impl<T: Hittable> Bvh<T> {
+    pub fn new(objects: Vec<T>) -> Self {
+        let mut bounded = Vec::new();
+        let mut unbounded = Vec::new();
+        for object in objects {
+            match object.bounding_box() {
+                Some(_) => bounded.push(Some(object)),
+                None => unbounded.push(object),
+            }
+        }
+
+        let mut build_primitives: Vec<BuildPrimitive> = bounded
+            .iter()
+            .enumerate()
+            .filter_map(|(index, object)| {
+                let bounds = object.as_ref()?.bounding_box()?;
+                Some(BuildPrimitive {
+                    index,
+                    bounds,
+                    centroid: bounds.centroid(),
+                })
+            })
+            .collect();
+
+        let mut nodes = Vec::new();
+        if !build_primitives.is_empty() {
+            build(&mut nodes, &mut build_primitives, 0, 0);
+        }
+
+        let primitives = build_primitives
+            .iter()
+            .filter_map(|p| bounded[p.index].take())
+            .collect();
+
+        Bvh {
+            primitives,
+            nodes,
+            unbounded,
+        }
+    }
+}
+
+impl<T: Hittable> Hittable for Bvh<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest_so_far = t_max;
+        let mut record: Option<HitRecord> = None;
+
+        if !self.nodes.is_empty() {
+            let inv_direction = Vector3::new(
+                1.0 / ray.direction.x,
+                1.0 / ray.direction.y,
+                1.0 / ray.direction.z,
+            );
+
+            let mut stack = [0usize; STACK_SIZE];
+            let mut stack_size = 0;
+            let mut node_index = 0;
+
+            loop {
+                let node = &self.nodes[node_index];
+                if node.bounds.hit(ray, inv_direction, t_min, closest_so_far) {
+                    match node.kind {
+                        NodeKind::Leaf { first, count } => {
+                            for primitive in &self.primitives[first..first + count] {
+                                if let Some(hit) = primitive.hit(ray, t_min, closest_so_far) {
+                                    closest_so_far = hit.t;
+                                    record = Some(hit);
+                                }
+                            }
+                        }
+                        NodeKind::Interior { second_child, axis } => {
+                            // Visit the child nearer the ray origin first so
+                            // that closest_so_far shrinks as early as possible.
+                            if inv_direction[axis] < 0.0 {
+                                stack[stack_size] = node_index + 1;
+                                node_index = second_child;
+                            } else {
+                                stack[stack_size] = second_child;
+                                node_index += 1;
+                            }
+                            stack_size += 1;
+                            continue;
+                        }
+                    }
+                }
+
+                if stack_size == 0 {
+                    break;
+                }
+                stack_size -= 1;
+                node_index = stack[stack_size];
+            }
+        }
+
+        for object in self.unbounded.iter() {
+            if let Some(hit) = object.hit(ray, t_min, closest_so_far) {
+                closest_so_far = hit.t;
+                record = Some(hit);
+            }
+        }
+
+        record
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        if self.unbounded.is_empty() {
+            self.nodes.first().map(|root| root.bounds)
+        } else {
+            None
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::sync::Arc;
+
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+    use crate::{
+        mesh::{Mesh, MeshData},
+        shapes::{Indexed, Material, Plane, Quad, Sphere, World},
+    };
+
+    fn material() -> Material {
+        Material::Lambetarian {
+            albedo: 0.5.into(),
+            bump: None,
+        }
+    }
+
+    fn point<R: Rng>(rng: &mut R, extent: f64) -> Vector3<f64> {
+        Vector3::new(
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+        )
+    }
+
+    // Spheres, quads, a mesh with a hierarchy of its own and an unbounded
+    // plane, for rays from anywhere around them.
+    #[test]
+    fn traversal_finds_the_closest_hit_of_the_flat_list() {
+        let mut rng = StdRng::seed_from_u64(3);
+        let mut world = World::new();
+        for _ in 0..200 {
+            let center = point(&mut rng, 10.0);
+            if rng.gen_bool(0.5) {
+                world.add(Sphere {
+                    center,
+                    radius: rng.gen_range(0.1..1.0),
+                    material: material(),
+                });
+            } else {
+                world.add(Quad {
+                    corner: center,
+                    u: point(&mut rng, 1.0),
+                    v: point(&mut rng, 1.0),
+                    material: material(),
+                });
+            }
+        }
+        let positions: Vec<_> = (0..150).map(|_| point(&mut rng, 5.0)).collect();
+        world.add(Mesh::new(
+            MeshData {
+                indices: (0..50).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect(),
+                positions,
+                normals: Vec::new(),
+                uvs: Vec::new(),
+            },
+            material(),
+        ));
+        world.add(Plane {
+            point: Vector3::new(0.0, -12.0, 0.0),
+            normal: Vector3::unit_y(),
+            material: material(),
+        });
+
+        // Tagged with their index, as scenes are, so that hits on different
+        // objects at the same distance tell apart.
+        let world = World {
+            objects: world
+                .objects
+                .into_iter()
+                .enumerate()
+                .map(|(index, object)| Arc::new(Indexed { object, index }) as Arc<dyn Hittable>)
+                .collect(),
+        };
+        let bvh = Bvh::new(world.objects.iter().map(Arc::clone).collect());
+        let mut hits = 0;
+        for _ in 0..20_000 {
+            let ray = Ray::new(point(&mut rng, 15.0), point(&mut rng, 1.0));
+            match (
+                world.hit(&ray, 0.001, f64::INFINITY),
+                bvh.hit(&ray, 0.001, f64::INFINITY),
+            ) {
+                (None, None) => {}
+                (Some(flat), Some(tree)) => {
+                    assert_eq!((flat.t, flat.p, flat.normal), (tree.t, tree.p, tree.normal));
+                    hits += 1;
+                }
+                (flat, tree) => panic!(
+                    "the flat list hit at {:?} and the BVH at {:?}",
+                    flat.map(|hit| hit.t),
+                    tree.map(|hit| hit.t)
+                ),
+            }
+        }
+        assert!(hits > 10_000, "only {hits} rays hit anything");
+    }
+}
@@ -1,6 +1,10 @@
 use cgmath::{InnerSpace, Vector3};
 
-use crate::{ray::Ray, vec::random_in_unit_disk};
+use crate::{
+    ray::{Differentials, Ray},
+    sampler::Sampler,
+    vec::sample_unit_disk,
+};
 
 pub struct Camera {
     origin: Vector3<f64>,
@@ -15,20 +19,21 @@ pub struct Camera {
 }
 
 impl Camera {
+    #[allow(clippy::too_many_arguments)]
     pub fn new(
         look_from: Vector3<f64>,
         look_at: Vector3<f64>,
         vup: Vector3<f64>,
         vertical_fov: f64,
-        aspect_ratio: f64,
         aperture: f64,
         focus_dist: f64,
         image_width: u32,
+        image_height: u32,
     ) -> Self {
         let theta = vertical_fov.to_radians();
         let h = (theta / 2.0).tan();
 
-        let image_height = (image_width as f64 / aspect_ratio) as u32;
+        let aspect_ratio = image_width as f64 / image_height as f64;
 
         let viewport_height = 2.0 * h;
         let viewport_width = aspect_ratio * viewport_height;
@@ -55,13 +60,27 @@ impl Camera {
         }
     }
 
-    pub fn get_ray(&self, s: f64, t: f64) -> Ray {
-        let rd = self.lens_radius * random_in_unit_disk();
+    // The ray carries differentials through the pixels to its right and
+    // above, which lie 1 / (width - 1) and 1 / (height - 1) away in `s` and
+    // `t` as the renderer maps pixels onto the film.
+    pub fn get_ray<S: Sampler + ?Sized>(&self, s: f64, t: f64, sampler: &mut S) -> Ray {
+        let rd = self.lens_radius * sample_unit_disk(sampler.get_2d());
         let offset = self.u * rd.x + self.v * rd.y;
 
-        Ray::new(
-            self.origin + offset,
-            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset,
-        )
+        let origin = self.origin + offset;
+        let direction =
+            self.lower_left_corner + s * self.horizontal + t * self.vertical - self.origin - offset;
+        let dx = self.horizontal / (self.image_width.max(2) - 1) as f64;
+        let dy = self.vertical / (self.image_height.max(2) - 1) as f64;
+
+        Ray {
+            differentials: Some(Differentials {
+                rx_origin: origin,
+                rx_direction: direction + dx,
+                ry_origin: origin,
+                ry_direction: direction + dy,
+            }),
+            ..Ray::new(origin, direction)
+        }
     }
 }
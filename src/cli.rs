@@ -0,0 +1,380 @@
+use std::path::PathBuf;
+
+use clap::{value_parser, Args, Parser, Subcommand, ValueEnum};
+use rand::SeedableRng;
+use rand_pcg::Pcg32;
+
+use rust_tracer::adaptive::AdaptiveSampling;
+use rust_tracer::denoise::Denoiser;
+use rust_tracer::filter::{self, FilterKind};
+use rust_tracer::integrator::{DebugChannel, IntegratorKind};
+use rust_tracer::sampler::SamplerKind;
+use rust_tracer::scene::{SceneError, SceneFile};
+use rust_tracer::scenes::random_world;
+use rust_tracer::tonemap::ToneMapper;
+use rust_tracer::{aov, output};
+
+#[derive(Parser)]
+#[command(version, about = "Ray Tracing in One Weekend in Rust")]
+pub struct Cli {
+    #[command(subcommand)]
+    pub command: Command,
+}
+
+#[derive(Subcommand)]
+pub enum Command {
+    /// Render a scene to an image file
+    Render {
+        #[command(flatten)]
+        scene: SceneArgs,
+        /// Image file to write
+        #[arg(short, long, default_value = "output.ppm")]
+        output: PathBuf,
+        /// Image format, guessed from the output extension if omitted
+        #[arg(short, long, value_enum)]
+        format: Option<ImageFormat>,
+    },
+    /// Print a summary of a scene
+    Info {
+        #[command(flatten)]
+        scene: SceneArgs,
+        /// Write the scene, including any overrides, to a TOML file
+        #[arg(long)]
+        save: Option<PathBuf>,
+    },
+    /// Time repeated renders of a scene
+    Bench {
+        #[command(flatten)]
+        scene: SceneArgs,
+        /// Number of timed renders
+        #[arg(short, long, default_value_t = 3)]
+        iterations: u32,
+    },
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ImageFormat {
+    /// 8-bit PNG
+    Png,
+    /// 16-bit PNG
+    Png16,
+    /// Binary PPM (P6)
+    Ppm,
+    /// Linear floating point PFM
+    Pfm,
+    /// Linear half float OpenEXR
+    Exr,
+    /// Linear full float OpenEXR
+    Exr32,
+    /// Linear Radiance RGBE
+    Hdr,
+}
+
+impl From<ImageFormat> for output::ImageFormat {
+    fn from(format: ImageFormat) -> Self {
+        match format {
+            ImageFormat::Png => output::ImageFormat::Png8,
+            ImageFormat::Png16 => output::ImageFormat::Png16,
+            ImageFormat::Ppm => output::ImageFormat::Ppm,
+            ImageFormat::Pfm => output::ImageFormat::Pfm,
+            ImageFormat::Exr => output::ImageFormat::ExrHalf,
+            ImageFormat::Exr32 => output::ImageFormat::ExrFloat,
+            ImageFormat::Hdr => output::ImageFormat::Hdr,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Integrator {
+    /// Path tracing with light sampling
+    Path,
+    /// Ambient occlusion
+    Ao,
+    /// Direct lighting only
+    Direct,
+    /// Whitted-style ray tracing
+    Whitted,
+}
+
+impl From<Integrator> for IntegratorKind {
+    fn from(integrator: Integrator) -> Self {
+        match integrator {
+            Integrator::Path => IntegratorKind::Path,
+            Integrator::Ao => IntegratorKind::ambient_occlusion(),
+            Integrator::Direct => IntegratorKind::Direct,
+            Integrator::Whitted => IntegratorKind::Whitted,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Sampler {
+    /// Independent uniform random numbers
+    Independent,
+    /// Jittered strata
+    Stratified,
+    /// Owen-scrambled Halton sequence
+    Halton,
+    /// Owen-scrambled Sobol sequence
+    Sobol,
+}
+
+impl From<Sampler> for SamplerKind {
+    fn from(sampler: Sampler) -> Self {
+        match sampler {
+            Sampler::Independent => SamplerKind::Independent,
+            Sampler::Stratified => SamplerKind::Stratified,
+            Sampler::Halton => SamplerKind::Halton,
+            Sampler::Sobol => SamplerKind::Sobol,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Filter {
+    /// Average of the samples within each pixel
+    Box,
+    /// Linear falloff
+    Tent,
+    /// Gaussian falloff
+    Gaussian,
+    /// Mitchell-Netravali cubic
+    Mitchell,
+    /// Windowed sinc
+    Lanczos,
+}
+
+impl From<Filter> for FilterKind {
+    fn from(filter: Filter) -> Self {
+        match filter {
+            Filter::Box => FilterKind::Box,
+            Filter::Tent => FilterKind::Tent,
+            Filter::Gaussian => FilterKind::Gaussian,
+            Filter::Mitchell => FilterKind::Mitchell,
+            Filter::Lanczos => FilterKind::Lanczos,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum ToneMap {
+    /// Clip to the displayable range
+    Clamp,
+    /// x / (1 + x)
+    Reinhard,
+    /// Uncharted 2 filmic curve
+    Hable,
+    /// Fitted ACES reference and output transforms
+    Aces,
+    /// AgX
+    Agx,
+}
+
+impl From<ToneMap> for ToneMapper {
+    fn from(tone_map: ToneMap) -> Self {
+        match tone_map {
+            ToneMap::Clamp => ToneMapper::Clamp,
+            ToneMap::Reinhard => ToneMapper::Reinhard,
+            ToneMap::Hable => ToneMapper::Hable,
+            ToneMap::Aces => ToneMapper::Aces,
+            ToneMap::Agx => ToneMapper::Agx,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Debug {
+    /// Shading normal
+    Normal,
+    /// Distance to the first hit
+    Depth,
+    /// Material albedo
+    Albedo,
+    /// Texture coordinates
+    Uv,
+    /// Index of the scene object
+    Object,
+    /// Index of the primitive within the object
+    Primitive,
+    /// Index of the material
+    Material,
+    /// Number of path tracer bounces
+    Bounces,
+}
+
+impl From<Debug> for DebugChannel {
+    fn from(debug: Debug) -> Self {
+        match debug {
+            Debug::Normal => DebugChannel::Normal,
+            Debug::Depth => DebugChannel::Depth,
+            Debug::Albedo => DebugChannel::Albedo,
+            Debug::Uv => DebugChannel::Uv,
+            Debug::Object => DebugChannel::Object,
+            Debug::Primitive => DebugChannel::Primitive,
+            Debug::Material => DebugChannel::Material,
+            Debug::Bounces => DebugChannel::Bounces,
+        }
+    }
+}
+
+#[derive(Clone, Copy, ValueEnum)]
+pub enum Aov {
+    /// Material albedo at the first hit
+    Albedo,
+    /// Shading normal at the first hit
+    Normal,
+    /// Distance to the first hit
+    Depth,
+    /// Light reflected once by a diffuse surface
+    DirectDiffuse,
+    /// Light reflected more than once after a diffuse surface
+    IndirectDiffuse,
+    /// Light reflected or refracted by metal and glass
+    Specular,
+    /// Light emitted by what the camera sees
+    Emission,
+    /// Coverage
+    Alpha,
+    /// Heatmap of the samples each pixel took
+    Samples,
+}
+
+impl From<Aov> for aov::Aov {
+    fn from(aov: Aov) -> Self {
+        match aov {
+            Aov::Albedo => aov::Aov::Albedo,
+            Aov::Normal => aov::Aov::Normal,
+            Aov::Depth => aov::Aov::Depth,
+            Aov::DirectDiffuse => aov::Aov::DirectDiffuse,
+            Aov::IndirectDiffuse => aov::Aov::IndirectDiffuse,
+            Aov::Specular => aov::Aov::Specular,
+            Aov::Emission => aov::Aov::Emission,
+            Aov::Alpha => aov::Aov::Alpha,
+            Aov::Samples => aov::Aov::Samples,
+        }
+    }
+}
+
+#[derive(Args)]
+pub struct SceneArgs {
+    /// Scene file; the random sphere scene is generated if omitted
+    #[arg(short, long)]
+    pub scene: Option<PathBuf>,
+    /// Image width in pixels
+    #[arg(long, value_parser = value_parser!(u32).range(1..))]
+    pub width: Option<u32>,
+    /// Image height in pixels
+    #[arg(long, value_parser = value_parser!(u32).range(1..))]
+    pub height: Option<u32>,
+    /// Samples per pixel
+    #[arg(long, value_parser = value_parser!(u32).range(1..))]
+    pub spp: Option<u32>,
+    /// Sample pixels adaptively until their noise is below this
+    #[arg(long)]
+    pub noise_threshold: Option<f64>,
+    /// Fewest samples per pixel with adaptive sampling
+    #[arg(long, value_parser = value_parser!(u32).range(1..))]
+    pub min_spp: Option<u32>,
+    /// How the random numbers of each sample are generated
+    #[arg(long, value_enum)]
+    pub sampler: Option<Sampler>,
+    /// Pixel reconstruction filter
+    #[arg(long, value_enum)]
+    pub filter: Option<Filter>,
+    /// Radius of the filter in pixels
+    #[arg(long)]
+    pub filter_radius: Option<f64>,
+    /// Maximum number of bounces
+    #[arg(long)]
+    pub depth: Option<u32>,
+    /// Bounces before paths may be ended by Russian roulette
+    #[arg(long)]
+    pub min_depth: Option<u32>,
+    /// Rendering algorithm
+    #[arg(long, value_enum)]
+    pub integrator: Option<Integrator>,
+    /// Render a debug view instead of the image
+    #[arg(long, value_enum)]
+    pub debug: Option<Debug>,
+    /// Write raw debug values rather than false colour
+    #[arg(long, requires = "debug")]
+    pub raw: bool,
+    /// Extra images to render alongside the beauty image, comma separated
+    #[arg(long, value_enum, value_delimiter = ',')]
+    pub aovs: Vec<Aov>,
+    /// Denoise the image, guided by albedo and normals
+    #[arg(long)]
+    pub denoise: bool,
+    /// Exposure adjustment in stops
+    #[arg(long, allow_negative_numbers = true)]
+    pub exposure: Option<f64>,
+    /// Colour temperature of the light in kelvin to balance to white
+    #[arg(long)]
+    pub white_balance: Option<f64>,
+    /// Tone mapping curve for 8 and 16-bit images
+    #[arg(long, value_enum)]
+    pub tone_map: Option<ToneMap>,
+    /// Seed for sampling and for the random sphere scene
+    #[arg(long)]
+    pub seed: Option<u64>,
+    /// Number of render threads, all cores if omitted
+    #[arg(short = 'j', long)]
+    pub threads: Option<usize>,
+}
+
+impl SceneArgs {
+    pub fn load(&self) -> Result<SceneFile, SceneError> {
+        let mut file = match &self.scene {
+            Some(path) => SceneFile::open(path)?,
+            None => random_world(&mut Pcg32::seed_from_u64(self.seed.unwrap_or(0))),
+        };
+
+        let render = &mut file.render;
+        render.width = self.width.unwrap_or(render.width);
+        render.height = self.height.unwrap_or(render.height);
+        render.samples_per_pixel = self.spp.unwrap_or(render.samples_per_pixel);
+        if self.noise_threshold.is_some() || self.min_spp.is_some() {
+            let adaptive = render
+                .adaptive
+                .get_or_insert_with(AdaptiveSampling::default);
+            adaptive.threshold = self.noise_threshold.unwrap_or(adaptive.threshold);
+            adaptive.min_samples = self.min_spp.unwrap_or(adaptive.min_samples);
+        }
+        if let Some(sampler) = self.sampler {
+            render.sampler = sampler.into();
+        }
+        if let Some(kind) = self.filter {
+            render.filter = filter::Filter::new(kind.into());
+        }
+        if let Some(radius) = self.filter_radius {
+            render.filter.radius = Some(radius);
+        }
+        render.display.exposure = self.exposure.unwrap_or(render.display.exposure);
+        if let Some(kelvin) = self.white_balance {
+            render.display.white_balance = Some(kelvin);
+        }
+        if let Some(tone_map) = self.tone_map {
+            render.display.tone_mapper = tone_map.into();
+        }
+        render.min_depth = self.min_depth.unwrap_or(render.min_depth);
+        render.max_depth = self.depth.unwrap_or(render.max_depth);
+        render.seed = self.seed.unwrap_or(render.seed);
+        if let Some(integrator) = self.integrator {
+            render.integrator = integrator.into();
+        }
+        if !self.aovs.is_empty() {
+            render.aovs = self.aovs.iter().map(|&aov| aov.into()).collect();
+        }
+        if self.denoise && render.denoise.is_none() {
+            render.denoise = Some(Denoiser::default());
+        }
+        if let Some(channel) = self.debug {
+            render.integrator = IntegratorKind::Debug {
+                channel: channel.into(),
+                raw: self.raw,
+            };
+        }
+
+        Ok(file)
+    }
+}
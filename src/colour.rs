@@ -13,8 +13,7 @@ impl<T> Colour<T>
 where
     rand::distributions::Standard: rand::distributions::Distribution<T>,
 {
-    pub fn random() -> Colour<T> {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng + ?Sized>(rng: &mut R) -> Colour<T> {
         Colour {
             r: rng.gen(),
             g: rng.gen(),
@@ -23,18 +22,8 @@ where
     }
 }
 
-impl From<Colour<f64>> for Colour<u32> {
-    fn from(item: Colour<f64>) -> Colour<u32> {
-        Colour {
-            r: (256.0 * item.r.clamp(0.0, 0.999)) as u32,
-            g: (256.0 * item.g.clamp(0.0, 0.999)) as u32,
-            b: (256.0 * item.b.clamp(0.0, 0.999)) as u32,
-        }
-    }
-}
-
 impl<T: Mul<Output = T> + Copy> Colour<T> {
-    pub(crate) fn new(r: T, g: T, b: T) -> Self {
+    pub fn new(r: T, g: T, b: T) -> Self {
         Colour { r, g, b }
     }
 
@@ -43,6 +32,19 @@ impl<T: Mul<Output = T> + Copy> Colour<T> {
     }
 }
 
+impl Colour<f64> {
+    // Rec. 709 relative luminance.
+    pub fn luminance(&self) -> f64 {
+        0.2126 * self.r + 0.7152 * self.g + 0.0722 * self.b
+    }
+
+    // Blue through green to red as `x` goes from 0 to 1.
+    pub fn heatmap(x: f64) -> Colour<f64> {
+        let x = x.clamp(0.0, 1.0);
+        Colour::new(x, 1.0 - (2.0 * x - 1.0).abs(), 1.0 - x)
+    }
+}
+
 impl<T: Add<Output = T> + Mul<Output = T> + Copy> Add for Colour<T> {
     type Output = Self;
 
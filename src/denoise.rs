@@ -0,0 +1,209 @@
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{colour::Colour, output::Framebuffer};
+
+// Edge-avoiding à-trous wavelet filter (Dammertz et al. 2010). Each pass blurs
+// with a 5x5 B3 spline kernel whose taps are spread twice as far apart as in
+// the previous pass, and every tap is weighted down where colour, normal or
+// albedo differ from the centre pixel so that edges survive. Lighting is
+// filtered with the albedo divided out, which keeps textures sharp.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct Denoiser {
+    pub iterations: u32,
+    pub colour_sigma: f64,
+    pub normal_sigma: f64,
+    pub albedo_sigma: f64,
+}
+
+impl Default for Denoiser {
+    fn default() -> Self {
+        Denoiser {
+            iterations: 4,
+            colour_sigma: 0.2,
+            normal_sigma: 0.3,
+            albedo_sigma: 0.1,
+        }
+    }
+}
+
+const KERNEL: [f64; 5] = [1.0 / 16.0, 1.0 / 4.0, 3.0 / 8.0, 1.0 / 4.0, 1.0 / 16.0];
+// Albedo below this is left in rather than divided out.
+const MIN_ALBEDO: f64 = 1e-3;
+
+fn distance_squared(a: Colour<f64>, b: Colour<f64>) -> f64 {
+    (a.r - b.r).powi(2) + (a.g - b.g).powi(2) + (a.b - b.b).powi(2)
+}
+
+fn map(c: Colour<f64>, f: impl Fn(f64) -> f64) -> Colour<f64> {
+    Colour::new(f(c.r), f(c.g), f(c.b))
+}
+
+fn demodulate(colour: Colour<f64>, albedo: Colour<f64>) -> Colour<f64> {
+    let divide = |c: f64, a: f64| if a > MIN_ALBEDO { c / a } else { c };
+    Colour::new(
+        divide(colour.r, albedo.r),
+        divide(colour.g, albedo.g),
+        divide(colour.b, albedo.b),
+    )
+}
+
+fn remodulate(colour: Colour<f64>, albedo: Colour<f64>) -> Colour<f64> {
+    let multiply = |c: f64, a: f64| if a > MIN_ALBEDO { c * a } else { c };
+    Colour::new(
+        multiply(colour.r, albedo.r),
+        multiply(colour.g, albedo.g),
+        multiply(colour.b, albedo.b),
+    )
+}
+
+impl Denoiser {
+    pub fn denoise(
+        &self,
+        beauty: &Framebuffer,
+        albedo: &Framebuffer,
+        normal: &Framebuffer,
+    ) -> Framebuffer {
+        let (width, height) = (beauty.width as i64, beauty.height as i64);
+
+        let mut current: Vec<Colour<f64>> = beauty
+            .pixels
+            .iter()
+            .zip(&albedo.pixels)
+            .map(|(&c, &a)| demodulate(c, a))
+            .collect();
+
+        for iteration in 0..self.iterations {
+            let step = 1i64 << iteration;
+            // Colours are compared after compressing their range so that
+            // bright highlights do not dominate, with a tighter tolerance on
+            // every pass as the noise goes down.
+            let colour_sigma = self.colour_sigma / step as f64;
+            let compressed: Vec<Colour<f64>> = current
+                .iter()
+                .map(|&c| map(c, |v| v.max(0.0) / (1.0 + v.max(0.0))))
+                .collect();
+
+            let filter = |p: usize| {
+                let (x, y) = (p as i64 % width, p as i64 / width);
+                let mut sum = Colour::new(0.0, 0.0, 0.0);
+                let mut total_weight = 0.0;
+
+                for (dy, ky) in (-2..=2).zip(KERNEL) {
+                    for (dx, kx) in (-2..=2).zip(KERNEL) {
+                        let qx = (x + dx * step).clamp(0, width - 1);
+                        let qy = (y + dy * step).clamp(0, height - 1);
+                        let q = (qy * width + qx) as usize;
+
+                        let colour_distance = distance_squared(compressed[p], compressed[q]);
+                        let normal_distance = distance_squared(normal.pixels[p], normal.pixels[q]);
+                        let albedo_distance = distance_squared(albedo.pixels[p], albedo.pixels[q]);
+
+                        let weight = kx
+                            * ky
+                            * (-colour_distance / (colour_sigma * colour_sigma)
+                                - normal_distance / (self.normal_sigma * self.normal_sigma)
+                                - albedo_distance / (self.albedo_sigma * self.albedo_sigma))
+                                .exp();
+
+                        sum = sum + current[q] * weight;
+                        total_weight += weight;
+                    }
+                }
+
+                sum * (1.0 / total_weight)
+            };
+            let next = (0..current.len()).into_par_iter().map(filter).collect();
+            current = next;
+        }
+
+        Framebuffer {
+            width: beauty.width,
+            height: beauty.height,
+            pixels: current
+                .into_iter()
+                .zip(&albedo.pixels)
+                .map(|(c, &a)| remodulate(c, a))
+                .collect(),
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const WIDTH: u32 = 8;
+    const HEIGHT: u32 = 4;
+
+    // An image with `left` in its left half and `right` in its right half.
+    fn halves(left: Colour<f64>, right: Colour<f64>) -> Framebuffer {
+        let pixels = (0..WIDTH * HEIGHT)
+            .map(|i| if i % WIDTH < WIDTH / 2 { left } else { right })
+            .collect();
+        Framebuffer {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels,
+        }
+    }
+
+    fn grey(value: f64) -> Colour<f64> {
+        Colour::new(value, value, value)
+    }
+
+    // The largest change the denoiser makes to any channel of any pixel.
+    fn largest_change(before: &Framebuffer, after: &Framebuffer) -> f64 {
+        before
+            .pixels
+            .iter()
+            .zip(&after.pixels)
+            .map(|(a, b)| distance_squared(*a, *b).sqrt())
+            .fold(0.0, f64::max)
+    }
+
+    #[test]
+    fn constant_images_are_left_alone() {
+        let beauty = halves(Colour::new(0.3, 0.6, 0.9), Colour::new(0.3, 0.6, 0.9));
+        let albedo = halves(grey(0.5), grey(0.5));
+        let normal = halves(Colour::new(0.0, 1.0, 0.0), Colour::new(0.0, 1.0, 0.0));
+
+        let denoised = Denoiser::default().denoise(&beauty, &albedo, &normal);
+        assert!(largest_change(&beauty, &denoised) < 1e-12);
+    }
+
+    // Lighting a little brighter on the right is blurred across the middle
+    // unless the normals or the albedo change there too.
+    #[test]
+    fn edges_in_the_guides_are_kept() {
+        let denoiser = Denoiser::default();
+        let lighting = halves(grey(1.0), grey(1.1));
+        let flat = halves(Colour::new(0.0, 1.0, 0.0), Colour::new(0.0, 1.0, 0.0));
+        let plain = halves(grey(0.7), grey(0.7));
+
+        let lit = |albedo: &Framebuffer| Framebuffer {
+            width: WIDTH,
+            height: HEIGHT,
+            pixels: lighting
+                .pixels
+                .iter()
+                .zip(&albedo.pixels)
+                .map(|(&l, &a)| remodulate(l, a))
+                .collect(),
+        };
+
+        let beauty = lit(&plain);
+        let blurred = denoiser.denoise(&beauty, &plain, &flat);
+        assert!(largest_change(&beauty, &blurred) > 0.01);
+
+        let creased = halves(Colour::new(0.0, 1.0, 0.0), Colour::new(1.0, 0.0, 0.0));
+        let kept = denoiser.denoise(&beauty, &plain, &creased);
+        assert!(largest_change(&beauty, &kept) < 1e-6);
+
+        let two_tone = halves(grey(0.5), grey(0.9));
+        let beauty = lit(&two_tone);
+        let kept = denoiser.denoise(&beauty, &two_tone, &flat);
+        assert!(largest_change(&beauty, &kept) < 1e-6);
+    }
+}
@@ -0,0 +1,122 @@
+use std::f64::consts::PI;
+
+use serde::{Deserialize, Serialize};
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum FilterKind {
+    Box,
+    Tent,
+    Gaussian,
+    Mitchell,
+    Lanczos,
+}
+
+// How every sample is spread over the pixels around it. Filters are separable
+// and reach `radius` pixels from the sample along each axis. The default box
+// of radius 0.5 averages the samples within each pixel.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct Filter {
+    #[serde(rename = "type")]
+    pub kind: FilterKind,
+    pub radius: Option<f64>,
+}
+
+impl Default for Filter {
+    fn default() -> Self {
+        Filter::new(FilterKind::Box)
+    }
+}
+
+impl Filter {
+    pub fn new(kind: FilterKind) -> Self {
+        Filter { kind, radius: None }
+    }
+
+    pub fn radius(&self) -> f64 {
+        self.radius.unwrap_or(match self.kind {
+            FilterKind::Box => 0.5,
+            FilterKind::Tent => 1.0,
+            FilterKind::Gaussian => 1.5,
+            FilterKind::Mitchell => 2.0,
+            FilterKind::Lanczos => 3.0,
+        })
+    }
+
+    // The weight along one axis of a sample `x` pixels away from a pixel
+    // centre; the weight of the sample is the product over both axes. Weights
+    // are normalised by their sum per pixel, so they need not integrate to
+    // one, and the Mitchell and Lanczos filters go negative.
+    //
+    // The box covers [-radius, radius), so that a sample on the edge between
+    // two pixels lands in exactly one of them; every other filter is zero at
+    // its radius anyway.
+    pub fn evaluate(&self, x: f64) -> f64 {
+        let radius = self.radius();
+        let (x, outside) = (x.abs(), !(-radius..radius).contains(&x));
+        match self.kind {
+            _ if outside => 0.0,
+            FilterKind::Box => 1.0,
+            // The other filters end at the radius on both sides.
+            _ if x >= radius => 0.0,
+            FilterKind::Tent => radius - x,
+            FilterKind::Gaussian => {
+                // Shifted down to reach zero at the radius.
+                let sigma = radius / 3.0;
+                gaussian(x, sigma) - gaussian(radius, sigma)
+            }
+            FilterKind::Mitchell => mitchell(2.0 * x / radius),
+            FilterKind::Lanczos => sinc(x) * sinc(x / radius),
+        }
+    }
+}
+
+fn gaussian(x: f64, sigma: f64) -> f64 {
+    (-x * x / (2.0 * sigma * sigma)).exp()
+}
+
+// The Mitchell-Netravali cubic with B = C = 1/3 over [0, 2).
+fn mitchell(x: f64) -> f64 {
+    const B: f64 = 1.0 / 3.0;
+    const C: f64 = 1.0 / 3.0;
+
+    let polynomial = if x > 1.0 {
+        (-B - 6.0 * C) * x * x * x
+            + (6.0 * B + 30.0 * C) * x * x
+            + (-12.0 * B - 48.0 * C) * x
+            + (8.0 * B + 24.0 * C)
+    } else {
+        (12.0 - 9.0 * B - 6.0 * C) * x * x * x
+            + (-18.0 + 12.0 * B + 6.0 * C) * x * x
+            + (6.0 - 2.0 * B)
+    };
+    polynomial / 6.0
+}
+
+fn sinc(x: f64) -> f64 {
+    if x.abs() < 1e-5 {
+        1.0
+    } else {
+        (PI * x).sin() / (PI * x)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A sample on the edge between two pixels is 0.5 after the centre of
+    // one and 0.5 before that of the other, and counts for exactly one.
+    #[test]
+    fn box_edges_belong_to_one_pixel() {
+        for radius in [None, Some(1.0)] {
+            let filter = Filter {
+                kind: FilterKind::Box,
+                radius,
+            };
+            let r = filter.radius();
+            assert_eq!(filter.evaluate(-r) + filter.evaluate(r), 1.0);
+        }
+    }
+}
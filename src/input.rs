@@ -0,0 +1,373 @@
+use std::{
+    fs::File,
+    io::{self, BufRead, BufReader, Read},
+    path::Path,
+};
+
+use crate::{colour::Colour, output::Framebuffer};
+
+fn srgb_to_linear(v: f64) -> f64 {
+    if v <= 0.04045 {
+        v / 12.92
+    } else {
+        ((v + 0.055) / 1.055).powf(2.4)
+    }
+}
+
+// Builds a framebuffer from interleaved values in [0, 1] with one to four
+// channels, of which the first three are colour or the first is grey; alpha
+// is dropped.
+fn from_samples(
+    width: u32,
+    height: u32,
+    channels: usize,
+    samples: impl Iterator<Item = f64>,
+    srgb: bool,
+) -> Framebuffer {
+    let decode = |v: f64| if srgb { srgb_to_linear(v) } else { v };
+    let samples: Vec<f64> = samples.map(decode).collect();
+    let pixels = samples
+        .chunks_exact(channels)
+        .map(|p| match channels {
+            1 | 2 => Colour::new(p[0], p[0], p[0]),
+            _ => Colour::new(p[0], p[1], p[2]),
+        })
+        .collect();
+
+    Framebuffer {
+        width,
+        height,
+        pixels,
+    }
+}
+
+fn read_png<R: Read>(reader: R, srgb: bool) -> io::Result<Framebuffer> {
+    let mut decoder = png::Decoder::new(reader);
+    decoder.set_transformations(png::Transformations::EXPAND);
+    let mut reader = decoder.read_info().map_err(io::Error::other)?;
+    let mut data = vec![0; reader.output_buffer_size()];
+    let info = reader.next_frame(&mut data).map_err(io::Error::other)?;
+    let data = &data[..info.buffer_size()];
+
+    let channels = info.color_type.samples();
+    Ok(match info.bit_depth {
+        png::BitDepth::Sixteen => {
+            let samples = data
+                .chunks_exact(2)
+                .map(|b| u16::from_be_bytes([b[0], b[1]]) as f64 / 65535.0);
+            from_samples(info.width, info.height, channels, samples, srgb)
+        }
+        _ => {
+            let samples = data.iter().map(|&b| b as f64 / 255.0);
+            from_samples(info.width, info.height, channels, samples, srgb)
+        }
+    })
+}
+
+fn read_jpeg<R: Read>(reader: R, srgb: bool) -> io::Result<Framebuffer> {
+    use jpeg_decoder::PixelFormat;
+
+    let mut decoder = jpeg_decoder::Decoder::new(reader);
+    let data = decoder.decode().map_err(io::Error::other)?;
+    let info = decoder
+        .info()
+        .ok_or_else(|| io::Error::other("missing JPEG header"))?;
+    let (width, height) = (info.width as u32, info.height as u32);
+
+    match info.pixel_format {
+        PixelFormat::L8 => {
+            let samples = data.iter().map(|&b| b as f64 / 255.0);
+            Ok(from_samples(width, height, 1, samples, srgb))
+        }
+        PixelFormat::L16 => {
+            let samples = data
+                .chunks_exact(2)
+                .map(|b| u16::from_ne_bytes([b[0], b[1]]) as f64 / 65535.0);
+            Ok(from_samples(width, height, 1, samples, srgb))
+        }
+        PixelFormat::RGB24 => {
+            let samples = data.iter().map(|&b| b as f64 / 255.0);
+            Ok(from_samples(width, height, 3, samples, srgb))
+        }
+        PixelFormat::CMYK32 => Err(io::Error::other("CMYK JPEG images are not supported")),
+    }
+}
+
+fn from_rgbe([r, g, b, e]: [u8; 4]) -> Colour<f64> {
+    if e == 0 {
+        return Colour::new(0.0, 0.0, 0.0);
+    }
+
+    let scale = 2f64.powi(e as i32 - 136);
+    Colour::new(
+        (r as f64 + 0.5) * scale,
+        (g as f64 + 0.5) * scale,
+        (b as f64 + 0.5) * scale,
+    )
+}
+
+fn invalid_hdr(message: &str) -> io::Error {
+    io::Error::new(io::ErrorKind::InvalidData, message)
+}
+
+// One scanline, either flat or run-length encoded a channel at a time as
+// Radiance itself writes them.
+fn read_hdr_scanline<R: Read>(reader: &mut R, width: usize) -> io::Result<Vec<[u8; 4]>> {
+    let mut first = [0; 4];
+    reader.read_exact(&mut first)?;
+
+    let encoded_width = ((first[2] as usize) << 8) | first[3] as usize;
+    let run_length_encoded =
+        (8..=0x7fff).contains(&width) && first[0] == 2 && first[1] == 2 && encoded_width == width;
+
+    let mut scanline = vec![[0; 4]; width];
+    if !run_length_encoded {
+        scanline[0] = first;
+        for pixel in &mut scanline[1..] {
+            reader.read_exact(pixel)?;
+        }
+        return Ok(scanline);
+    }
+
+    for channel in 0..4 {
+        let mut x = 0;
+        while x < width {
+            let mut count = [0; 1];
+            reader.read_exact(&mut count)?;
+            let (run, count) = match count[0] {
+                c if c > 128 => (true, (c - 128) as usize),
+                c => (false, c as usize),
+            };
+            if count == 0 || x + count > width {
+                return Err(invalid_hdr("bad run length in HDR scanline"));
+            }
+
+            if run {
+                let mut value = [0; 1];
+                reader.read_exact(&mut value)?;
+                for pixel in &mut scanline[x..x + count] {
+                    pixel[channel] = value[0];
+                }
+            } else {
+                let mut values = vec![0; count];
+                reader.read_exact(&mut values)?;
+                for (pixel, value) in scanline[x..x + count].iter_mut().zip(values) {
+                    pixel[channel] = value;
+                }
+            }
+            x += count;
+        }
+    }
+    Ok(scanline)
+}
+
+// Radiance RGBE stored top row first, as `write_hdr` and most tools write it.
+fn read_hdr<R: BufRead>(mut reader: R) -> io::Result<Framebuffer> {
+    let mut line = String::new();
+    reader.read_line(&mut line)?;
+    if !line.starts_with("#?") {
+        return Err(invalid_hdr("not a Radiance HDR image"));
+    }
+
+    loop {
+        line.clear();
+        if reader.read_line(&mut line)? == 0 {
+            return Err(invalid_hdr("HDR header is not terminated"));
+        }
+        let line = line.trim();
+        if line.is_empty() {
+            break;
+        }
+        if let Some(format) = line.strip_prefix("FORMAT=") {
+            if format != "32-bit_rle_rgbe" {
+                return Err(invalid_hdr("only RGBE HDR images are supported"));
+            }
+        }
+    }
+
+    line.clear();
+    reader.read_line(&mut line)?;
+    let resolution: Vec<&str> = line.split_whitespace().collect();
+    let (height, width) = match resolution[..] {
+        ["-Y", height, "+X", width] => (height.parse(), width.parse()),
+        _ => return Err(invalid_hdr("only -Y +X HDR orientation is supported")),
+    };
+    let (height, width) = match (height, width) {
+        (Ok(height), Ok(width)) if height > 0 && width > 0 => (height, width),
+        _ => return Err(invalid_hdr("bad HDR resolution")),
+    };
+
+    let mut pixels = Vec::with_capacity(width * height);
+    for _ in 0..height {
+        let scanline = read_hdr_scanline(&mut reader, width)?;
+        pixels.extend(scanline.into_iter().map(from_rgbe));
+    }
+
+    Ok(Framebuffer {
+        width: width as u32,
+        height: height as u32,
+        pixels,
+    })
+}
+
+// The first layer with R, G and B channels.
+fn read_exr(path: &Path) -> io::Result<Framebuffer> {
+    use exr::prelude::*;
+
+    let image = read_first_rgba_layer_from_file(
+        path,
+        |resolution, _| Framebuffer::new(resolution.width() as u32, resolution.height() as u32),
+        |framebuffer, position, (r, g, b, _): (f32, f32, f32, f32)| {
+            let colour = Colour::new(r as f64, g as f64, b as f64);
+            framebuffer.set(position.x() as u32, position.y() as u32, colour);
+        },
+    )
+    .map_err(|e| match e {
+        exr::error::Error::Io(e) => e,
+        e => io::Error::other(e),
+    })?;
+
+    Ok(image.layer_data.channel_data.pixels)
+}
+
+// Reads a PNG, JPEG, Radiance HDR or OpenEXR image, chosen by extension. The
+// 8 and 16-bit formats are decoded from sRGB into linear values when `srgb`
+// is set, and otherwise just scaled to [0, 1]; the float formats are linear
+// already.
+pub fn read_image(path: &Path, srgb: bool) -> io::Result<Framebuffer> {
+    let extension = path
+        .extension()
+        .and_then(|extension| extension.to_str())
+        .unwrap_or_default()
+        .to_ascii_lowercase();
+
+    match extension.as_str() {
+        "exr" => read_exr(path),
+        "png" => read_png(BufReader::new(File::open(path)?), srgb),
+        "jpg" | "jpeg" => read_jpeg(BufReader::new(File::open(path)?), srgb),
+        "hdr" => read_hdr(BufReader::new(File::open(path)?)),
+        _ => Err(io::Error::new(
+            io::ErrorKind::Unsupported,
+            "cannot tell the image format from the extension",
+        )),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{
+        output::{write_image, ImageFormat},
+        tonemap::DisplayTransform,
+    };
+
+    #[test]
+    fn hdr_round_trip() {
+        let colours = [
+            Colour::new(0.0, 0.0, 0.0),
+            Colour::new(1.0, 1.0, 1.0),
+            Colour::new(0.5, 0.25, 0.125),
+            Colour::new(1000.0, 3.0, 0.01),
+            Colour::new(1e-6, 2e-6, 4e-6),
+            Colour::new(0.9, 0.0, 17.5),
+        ];
+        let image = Framebuffer {
+            width: 3,
+            height: 2,
+            pixels: colours.to_vec(),
+        };
+
+        let path = std::env::temp_dir().join(format!("rust-tracer-{}.hdr", std::process::id()));
+        write_image(
+            &path,
+            ImageFormat::Hdr,
+            &image,
+            &DisplayTransform::default(),
+        )
+        .unwrap();
+        let read = read_image(&path, false).unwrap();
+        std::fs::remove_file(&path).unwrap();
+
+        assert_eq!((read.width, read.height), (3, 2));
+        for (written, read) in image.pixels.iter().zip(&read.pixels) {
+            // Every channel shares the exponent of the largest, which keeps
+            // eight bits of it.
+            let tolerance = written.r.max(written.g).max(written.b) / 128.0;
+            for (a, b) in [
+                (written.r, read.r),
+                (written.g, read.g),
+                (written.b, read.b),
+            ] {
+                assert!((a - b).abs() <= tolerance, "{written:?} read as {read:?}");
+            }
+        }
+    }
+
+    // Two scanlines of eight pixels, each channel run-length encoded as
+    // Radiance writes them: the colour channels as a run of five and three
+    // literal values, the shared exponent as a single run.
+    #[test]
+    fn reads_run_length_encoded_hdr() {
+        let mut data = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 2 +X 8\n".to_vec();
+        for row in 0..2u8 {
+            data.extend([2, 2, 0, 8]);
+            for channel in 0..3u8 {
+                let value = 64 * (channel + 1) + row;
+                data.extend([128 + 5, value, 3, value, 0, value / 2]);
+            }
+            data.extend([128 + 8, 129]);
+        }
+
+        let image = read_hdr(&data[..]).unwrap();
+        assert_eq!((image.width, image.height), (8, 2));
+        for row in 0..2 {
+            for x in 0..8 {
+                let rgbe = |channel: u8| {
+                    let value = 64 * (channel + 1) + row as u8;
+                    match x {
+                        6 => 0,
+                        7 => value / 2,
+                        _ => value,
+                    }
+                };
+                let expected = from_rgbe([rgbe(0), rgbe(1), rgbe(2), 129]);
+                let pixel = image.get(x, row);
+                assert_eq!(
+                    (pixel.r, pixel.g, pixel.b),
+                    (expected.r, expected.g, expected.b)
+                );
+            }
+        }
+        // The exponent of 129 scales by 2^-7: mantissa 64 + 0.5 reads 0.50390625.
+        assert_eq!(image.get(0, 0).r, 64.5 / 128.0);
+    }
+
+    fn png(bit_depth: png::BitDepth, data: &[u8]) -> Vec<u8> {
+        let mut bytes = Vec::new();
+        let mut encoder = png::Encoder::new(&mut bytes, 3, 1);
+        encoder.set_color(png::ColorType::Grayscale);
+        encoder.set_depth(bit_depth);
+        let mut writer = encoder.write_header().unwrap();
+        writer.write_image_data(data).unwrap();
+        writer.finish().unwrap();
+        bytes
+    }
+
+    // Black, sRGB middle grey (128 of 255, or the same in 16 bits) and white.
+    #[test]
+    fn png_decodes_srgb_to_linear() {
+        let eight = png(png::BitDepth::Eight, &[0, 128, 255]);
+        let sixteen = png(png::BitDepth::Sixteen, &[0, 0, 128, 128, 255, 255]);
+        for bytes in [eight, sixteen] {
+            let linear = read_png(&bytes[..], true).unwrap();
+            let grey: Vec<f64> = linear.pixels.iter().map(|c| c.r).collect();
+            assert_eq!(grey[0], 0.0);
+            assert!((grey[1] - 0.215861).abs() < 1e-5, "{grey:?}");
+            assert_eq!(grey[2], 1.0);
+            assert!(linear.pixels.iter().all(|c| c.r == c.g && c.g == c.b));
+
+            let data = read_png(&bytes[..], false).unwrap();
+            assert!((data.pixels[1].r - 128.0 / 255.0).abs() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,112 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Matrix, Matrix4, SquareMatrix, Vector3};
+
+use crate::{
+    aabb::Aabb,
+    ray::{Differentials, Ray},
+    sampler::Sampler,
+    shapes::{HitRecord, Hittable},
+};
+
+pub struct Instance {
+    object: Arc<dyn Hittable>,
+    transform: Matrix4<f64>,
+    inverse: Matrix4<f64>,
+}
+
+fn transform_point(m: &Matrix4<f64>, p: Vector3<f64>) -> Vector3<f64> {
+    (m * p.extend(1.0)).truncate()
+}
+
+fn transform_vector(m: &Matrix4<f64>, v: Vector3<f64>) -> Vector3<f64> {
+    (m * v.extend(0.0)).truncate()
+}
+
+impl Instance {
+    pub fn new(object: Arc<dyn Hittable>, transform: Matrix4<f64>) -> Option<Self> {
+        Some(Instance {
+            object,
+            transform,
+            inverse: transform.invert()?,
+        })
+    }
+}
+
+impl Hittable for Instance {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        // The direction is not renormalised so that t is the same in object
+        // and world space.
+        let local_ray = Ray {
+            origin: transform_point(&self.inverse, ray.origin),
+            direction: transform_vector(&self.inverse, ray.direction),
+            differentials: ray.differentials.map(|d| Differentials {
+                rx_origin: transform_point(&self.inverse, d.rx_origin),
+                rx_direction: transform_vector(&self.inverse, d.rx_direction),
+                ry_origin: transform_point(&self.inverse, d.ry_origin),
+                ry_direction: transform_vector(&self.inverse, d.ry_direction),
+            }),
+        };
+
+        let mut record = self.object.hit(&local_ray, t_min, t_max)?;
+        let normal_matrix = self.inverse.transpose();
+        record.p = transform_point(&self.transform, record.p);
+        record.normal = transform_vector(&normal_matrix, record.normal).normalize();
+        // Texture coordinates are the same in both spaces, so only the
+        // position and normal changes need bringing back.
+        if let Some(footprint) = &mut record.footprint {
+            footprint.dpdx = transform_vector(&self.transform, footprint.dpdx);
+            footprint.dpdy = transform_vector(&self.transform, footprint.dpdy);
+            footprint.dndx = transform_vector(&normal_matrix, footprint.dndx);
+            footprint.dndy = transform_vector(&normal_matrix, footprint.dndy);
+        }
+
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(self.object.bounding_box()?.transform(&self.transform))
+    }
+
+    // Directions are taken into object space by the inverse transform, which
+    // stretches solid angle around a unit direction d by |det M| / |M d|^3.
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let local_direction = transform_vector(&self.inverse, direction.normalize());
+        let pdf = self
+            .object
+            .pdf_value(transform_point(&self.inverse, origin), local_direction);
+        pdf * self.inverse.determinant().abs() / local_direction.magnitude().powi(3)
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let local_origin = transform_point(&self.inverse, origin);
+        transform_vector(&self.transform, self.object.random(local_origin, sampler))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use cgmath::Deg;
+
+    use super::*;
+    use crate::shapes::{assert_pdf_normalised, Material, Sphere};
+
+    // A sphere squashed into an ellipsoid is still sampled over the directions
+    // it covers with a density that integrates to one.
+    #[test]
+    fn transformed_pdf_integrates_to_one() {
+        let sphere = Sphere {
+            center: Vector3::new(0.0, 0.0, 0.0),
+            radius: 1.0,
+            material: Material::Lambetarian {
+                albedo: 0.5.into(),
+                bump: None,
+            },
+        };
+        let transform = Matrix4::from_translation(Vector3::new(0.2, -0.1, 0.0))
+            * Matrix4::from_angle_y(Deg(30.0))
+            * Matrix4::from_nonuniform_scale(2.0, 0.5, 1.0);
+        let instance = Instance::new(Arc::new(sphere), transform).unwrap();
+        assert_pdf_normalised(&instance, Vector3::new(0.5, 0.3, 2.5));
+    }
+}
@@ -0,0 +1,490 @@
+use cgmath::InnerSpace;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    aov::{Aov, Aovs},
+    colour::Colour,
+    ray::Ray,
+    render::{splitmix64, Background, Heuristic, RenderSettings},
+    sampler::Sampler,
+    shapes::{HitRecord, Hittable, World},
+    vec::sample_unit_sphere,
+};
+
+// Estimates the light arriving at the camera along one camera ray. The render
+// loop generates the rays and averages the samples of each pixel.
+pub trait Integrator: Sync {
+    fn radiance<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        sampler: &mut S,
+    ) -> Colour<f64>;
+
+    // Like `radiance`, but also records the arbitrary output variables of the
+    // sample. Integrators that do not split up their light only fill in the
+    // ones that depend on the first hit.
+    fn radiance_with_aovs<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        aovs: &mut Aovs,
+        sampler: &mut S,
+    ) -> Colour<f64> {
+        aovs.record_first_hit(ray, hittable.hit(ray, 0.001, f64::INFINITY).as_ref());
+        self.radiance(ray, hittable, lights, sampler)
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum IntegratorKind {
+    Path,
+    AmbientOcclusion {
+        #[serde(default = "default_occlusion_samples")]
+        samples: u32,
+        distance: Option<f64>,
+    },
+    Direct,
+    Whitted,
+    Debug {
+        channel: DebugChannel,
+        #[serde(default)]
+        raw: bool,
+    },
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum DebugChannel {
+    Normal,
+    Depth,
+    Albedo,
+    Uv,
+    Object,
+    Primitive,
+    Material,
+    Bounces,
+}
+
+fn default_occlusion_samples() -> u32 {
+    16
+}
+
+impl IntegratorKind {
+    pub fn ambient_occlusion() -> Self {
+        IntegratorKind::AmbientOcclusion {
+            samples: default_occlusion_samples(),
+            distance: None,
+        }
+    }
+}
+
+fn black() -> Colour<f64> {
+    Colour::new(0.0, 0.0, 0.0)
+}
+
+// Estimates the light reaching `hit_record` straight from a point sampled on
+// one of the lights. With a heuristic the estimate is weighted against the
+// chance that the BSDF would have picked the same direction.
+fn sample_lights<T: Hittable, S: Sampler + ?Sized>(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    hittable: &T,
+    lights: &World,
+    heuristic: Option<Heuristic>,
+    mut sampler: &mut S,
+) -> Colour<f64> {
+    let material = hit_record.material;
+
+    let direction = lights.random(hit_record.p, &mut sampler);
+    let light_pdf = lights.pdf_value(hit_record.p, direction);
+    if light_pdf <= 0.0 {
+        return black();
+    }
+
+    let scattering = material.scattering(ray, hit_record, direction);
+    if scattering.r + scattering.g + scattering.b <= 0.0 {
+        return black();
+    }
+
+    let Some(light) = hittable.hit(&Ray::new(hit_record.p, direction), 0.001, f64::INFINITY) else {
+        return black();
+    };
+
+    let weight = match heuristic {
+        Some(heuristic) => {
+            let scattering_pdf = material.scattering_pdf(ray, hit_record, direction);
+            heuristic.weight(light_pdf, scattering_pdf)
+        }
+        None => 1.0,
+    };
+    scattering.mul_element_wise(light.material.emitted(&light)) * (weight / light_pdf)
+}
+
+// Emission seen by a ray the BSDF sampled with `scattering_pdf`, weighted
+// against the chance that light sampling would have found it instead.
+fn weighted_emission(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    lights: &World,
+    heuristic: Heuristic,
+    scattering_pdf: Option<f64>,
+) -> Colour<f64> {
+    let emitted = hit_record.material.emitted(hit_record);
+    match scattering_pdf {
+        Some(scattering_pdf) => {
+            let light_pdf = lights.pdf_value(ray.origin, ray.direction);
+            emitted * heuristic.weight(scattering_pdf, light_pdf)
+        }
+        None => emitted,
+    }
+}
+
+pub struct PathTracer {
+    pub min_depth: u32,
+    pub max_depth: u32,
+    pub heuristic: Heuristic,
+    pub background: Background,
+}
+
+impl PathTracer {
+    pub fn new(settings: &RenderSettings) -> Self {
+        PathTracer {
+            min_depth: settings.min_depth,
+            max_depth: settings.max_depth,
+            heuristic: settings.heuristic,
+            background: settings.background,
+        }
+    }
+}
+
+impl Integrator for PathTracer {
+    fn radiance<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        sampler: &mut S,
+    ) -> Colour<f64> {
+        self.trace(ray, hittable, lights, &mut Aovs::default(), sampler)
+            .0
+    }
+
+    fn radiance_with_aovs<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        aovs: &mut Aovs,
+        sampler: &mut S,
+    ) -> Colour<f64> {
+        self.trace(ray, hittable, lights, aovs, sampler).0
+    }
+}
+
+impl PathTracer {
+    // Follows a single path, adding up the light it gathers at each hit
+    // weighted by the product of the attenuations so far, and returns it with
+    // the number of bounces taken. Past `min_depth` bounces the path survives
+    // with a probability that follows that throughput, so dark paths stop
+    // early without biasing the estimate.
+    pub fn trace<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        aovs: &mut Aovs,
+        sampler: &mut S,
+    ) -> (Colour<f64>, u32) {
+        let mut radiance = black();
+        let mut bounces = 0;
+        // Whether the first bounce was diffuse, which decides the lighting
+        // AOV everything the path gathers goes to.
+        let mut diffuse = true;
+        let mut throughput = Colour::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+        // The density the BSDF sampled `ray` with, or None for camera rays and
+        // delta bounces, for which the lights could not have been sampled.
+        let mut scattering_pdf = None;
+
+        for depth in 0..self.max_depth {
+            let hit_record = hittable.hit(&ray, 0.001, f64::INFINITY);
+            if depth == 0 {
+                aovs.record_first_hit(&ray, hit_record.as_ref());
+            }
+
+            let Some(hit_record) = hit_record else {
+                let background = throughput.mul_element_wise(self.background.colour(&ray));
+                radiance = radiance + background;
+                aovs.add(Aov::lighting(depth, diffuse), background);
+                break;
+            };
+            let material = hit_record.material;
+            if depth == 0 {
+                diffuse = material.is_diffuse();
+            }
+
+            let emitted =
+                weighted_emission(&ray, &hit_record, lights, self.heuristic, scattering_pdf);
+            let emitted = throughput.mul_element_wise(emitted);
+            radiance = radiance + emitted;
+            aovs.add(Aov::lighting(depth, diffuse), emitted);
+
+            if !lights.objects.is_empty() && !material.is_delta(&hit_record) {
+                let heuristic = Some(self.heuristic);
+                let direct = sample_lights(&ray, &hit_record, hittable, lights, heuristic, sampler);
+                let direct = throughput.mul_element_wise(direct);
+                radiance = radiance + direct;
+                aovs.add(Aov::lighting(depth + 1, diffuse), direct);
+            }
+
+            let Some(scattered_ray) = material.scatter(&ray, &hit_record, sampler) else {
+                break;
+            };
+            throughput = throughput.mul_element_wise(scattered_ray.attenuation);
+
+            if depth + 1 >= self.min_depth {
+                let survival = throughput.r.max(throughput.g).max(throughput.b).min(0.95);
+                if sampler.get_1d() >= survival {
+                    break;
+                }
+                throughput = throughput * (1.0 / survival);
+            }
+
+            bounces = depth + 1;
+            scattering_pdf = scattered_ray.pdf;
+            ray = scattered_ray.ray;
+        }
+
+        (radiance, bounces)
+    }
+}
+
+// The fraction of a cosine weighted hemisphere around the first hit that is
+// not blocked within `distance`, as a grey level.
+pub struct AmbientOcclusion {
+    pub samples: u32,
+    pub distance: f64,
+}
+
+impl Integrator for AmbientOcclusion {
+    fn radiance<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        _lights: &World,
+        sampler: &mut S,
+    ) -> Colour<f64> {
+        let Some(hit_record) = hittable.hit(ray, 0.001, f64::INFINITY) else {
+            return Colour::new(1.0, 1.0, 1.0);
+        };
+
+        let unoccluded = (0..self.samples)
+            .filter(|_| {
+                let direction =
+                    (hit_record.normal + sample_unit_sphere(sampler.get_2d())).normalize();
+                let occluder = Ray::new(hit_record.p, direction);
+                hittable.hit(&occluder, 0.001, self.distance).is_none()
+            })
+            .count();
+
+        let visibility = unoccluded as f64 / self.samples.max(1) as f64;
+        Colour::new(visibility, visibility, visibility)
+    }
+}
+
+// Only light that reaches the camera after a single non-specular bounce, with
+// mirrors and glass followed up to `max_depth`.
+pub struct DirectLighting {
+    pub max_depth: u32,
+    pub heuristic: Heuristic,
+    pub background: Background,
+}
+
+impl Integrator for DirectLighting {
+    fn radiance<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        sampler: &mut S,
+    ) -> Colour<f64> {
+        let mut radiance = black();
+        let mut throughput = Colour::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for _ in 0..self.max_depth {
+            let Some(hit_record) = hittable.hit(&ray, 0.001, f64::INFINITY) else {
+                radiance = radiance + throughput.mul_element_wise(self.background.colour(&ray));
+                break;
+            };
+            let material = hit_record.material;
+            radiance = radiance + throughput.mul_element_wise(material.emitted(&hit_record));
+
+            let Some(scattered_ray) = material.scatter(&ray, &hit_record, sampler) else {
+                break;
+            };
+
+            if scattered_ray.pdf.is_none() {
+                throughput = throughput.mul_element_wise(scattered_ray.attenuation);
+                ray = scattered_ray.ray;
+                continue;
+            }
+
+            if !lights.objects.is_empty() {
+                let heuristic = Some(self.heuristic);
+                let direct = sample_lights(&ray, &hit_record, hittable, lights, heuristic, sampler);
+                radiance = radiance + throughput.mul_element_wise(direct);
+            }
+
+            let bounce = &scattered_ray.ray;
+            let incoming = match hittable.hit(bounce, 0.001, f64::INFINITY) {
+                Some(light) => {
+                    weighted_emission(bounce, &light, lights, self.heuristic, scattered_ray.pdf)
+                }
+                None => self.background.colour(bounce),
+            };
+            throughput = throughput.mul_element_wise(scattered_ray.attenuation);
+            radiance = radiance + throughput.mul_element_wise(incoming);
+            break;
+        }
+
+        radiance
+    }
+}
+
+// Classic recursive ray tracing: diffuse surfaces only see the lights directly
+// while metal and glass are followed up to `max_depth`.
+pub struct Whitted {
+    pub max_depth: u32,
+    pub background: Background,
+}
+
+impl Integrator for Whitted {
+    fn radiance<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        sampler: &mut S,
+    ) -> Colour<f64> {
+        let mut radiance = black();
+        let mut throughput = Colour::new(1.0, 1.0, 1.0);
+        let mut ray = *ray;
+
+        for _ in 0..self.max_depth {
+            let Some(hit_record) = hittable.hit(&ray, 0.001, f64::INFINITY) else {
+                radiance = radiance + throughput.mul_element_wise(self.background.colour(&ray));
+                break;
+            };
+            let material = hit_record.material;
+            radiance = radiance + throughput.mul_element_wise(material.emitted(&hit_record));
+
+            if material.is_diffuse() {
+                if !lights.objects.is_empty() {
+                    let direct = sample_lights(&ray, &hit_record, hittable, lights, None, sampler);
+                    radiance = radiance + throughput.mul_element_wise(direct);
+                }
+                break;
+            }
+
+            let Some(scattered_ray) = material.scatter(&ray, &hit_record, sampler) else {
+                break;
+            };
+            throughput = throughput.mul_element_wise(scattered_ray.attenuation);
+            ray = scattered_ray.ray;
+        }
+
+        radiance
+    }
+}
+
+// Shows what the first hit of each camera ray records, or how many bounces
+// the path tracer takes, either as false colour or as raw values meant for
+// float image formats.
+pub struct DebugView {
+    pub channel: DebugChannel,
+    pub raw: bool,
+    pub path_tracer: PathTracer,
+}
+
+fn grey(value: f64) -> Colour<f64> {
+    Colour::new(value, value, value)
+}
+
+fn id_colour(id: u64) -> Colour<f64> {
+    let hash = splitmix64(id);
+    let channel = |shift: u32| ((hash >> shift) & 0xff) as f64 / 255.0;
+    Colour::new(channel(0), channel(8), channel(16))
+}
+
+impl DebugView {
+    fn id(&self, id: u64) -> Colour<f64> {
+        if self.raw {
+            grey(id as f64)
+        } else {
+            id_colour(id)
+        }
+    }
+}
+
+impl Integrator for DebugView {
+    fn radiance<T: Hittable, S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hittable: &T,
+        lights: &World,
+        sampler: &mut S,
+    ) -> Colour<f64> {
+        if self.channel == DebugChannel::Bounces {
+            let (_, bounces) =
+                self.path_tracer
+                    .trace(ray, hittable, lights, &mut Aovs::default(), sampler);
+            if self.raw {
+                return grey(bounces as f64);
+            }
+            let max_depth = self.path_tracer.max_depth.max(1);
+            return Colour::heatmap(bounces as f64 / max_depth as f64);
+        }
+
+        let Some(hit_record) = hittable.hit(ray, 0.001, f64::INFINITY) else {
+            return black();
+        };
+
+        match self.channel {
+            DebugChannel::Normal => {
+                let n = hit_record.normal;
+                if self.raw {
+                    Colour::new(n.x, n.y, n.z)
+                } else {
+                    Colour::new(0.5 * (n.x + 1.0), 0.5 * (n.y + 1.0), 0.5 * (n.z + 1.0))
+                }
+            }
+            // Camera rays are about as long as the focus distance, so t is
+            // roughly in units of it.
+            DebugChannel::Depth => {
+                if self.raw {
+                    grey(hit_record.t * ray.direction.magnitude())
+                } else {
+                    grey(1.0 / (1.0 + hit_record.t))
+                }
+            }
+            DebugChannel::Albedo => hit_record.material.albedo(&hit_record),
+            DebugChannel::Uv => {
+                let uv = hit_record.uv;
+                if self.raw {
+                    Colour::new(uv.x, uv.y, 0.0)
+                } else {
+                    Colour::new(uv.x.rem_euclid(1.0), uv.y.rem_euclid(1.0), 0.0)
+                }
+            }
+            DebugChannel::Object => self.id(hit_record.object as u64),
+            DebugChannel::Primitive => self.id(hit_record.primitive as u64),
+            DebugChannel::Material => self.id(hit_record.material_index as u64),
+            DebugChannel::Bounces => unreachable!(),
+        }
+    }
+}
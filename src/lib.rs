@@ -0,0 +1,25 @@
+pub mod aabb;
+pub mod adaptive;
+pub mod aov;
+pub mod bvh;
+pub mod camera;
+pub mod colour;
+pub mod denoise;
+pub mod filter;
+pub mod input;
+pub mod instance;
+pub mod integrator;
+pub mod mesh;
+pub mod noise;
+pub mod obj;
+pub mod output;
+pub mod ray;
+pub mod render;
+pub mod sampler;
+pub mod scene;
+pub mod scenes;
+pub mod shapes;
+pub mod texture;
+pub mod tonemap;
+pub mod vec;
+pub mod volume;
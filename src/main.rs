@@ -1,169 +1,139 @@
+use std::error::Error;
 use std::io::Write;
-use std::time::Instant;
-use std::{fs::File, io::Error};
-
-use cgmath::{InnerSpace, Vector3};
-use colour::Colour;
-use rand::Rng;
-use ray::Ray;
-use shapes::Hittable;
-
-use crate::camera::Camera;
-use crate::shapes::{Material, Sphere, World};
-
-mod camera;
-mod colour;
-mod ray;
-mod shapes;
-mod vec;
-
-fn ray_colour<T: Hittable>(ray: &Ray, hittable: &T, depth: u32) -> Colour<f64> {
-    if depth == 0 {
-        Colour::new(0.0, 0.0, 0.0)
-    } else if let Some(hit_record) = hittable.hit(ray, 0.001, f64::INFINITY) {
-        if let Some(scattered_ray) = hit_record.material.scatter(ray, &hit_record) {
-            scattered_ray.attenuation.mul_element_wise(ray_colour(
-                &scattered_ray.ray,
-                hittable,
-                depth - 1,
-            ))
-        } else {
-            Colour::new(1.0, 1.0, 1.0)
-        }
-    } else {
-        let unit_direction = ray.direction.normalize();
-        let t = 0.5 * (unit_direction.y + 1.0);
-        Colour::new(
-            (1.0 - t) + t * 0.5,
-            (1.0 - t) + t * 0.7,
-            (1.0 - t) + t * 1.0,
-        )
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+use clap::Parser;
+
+use rust_tracer::bvh::Bvh;
+use rust_tracer::output::{write_layers, Framebuffer, ImageFormat};
+use rust_tracer::render::render;
+use rust_tracer::scene::{Scene, SceneFile};
+use rust_tracer::shapes::Hittable;
+
+use crate::cli::{Cli, Command, SceneArgs};
+
+mod cli;
+
+type SceneBvh = Bvh<Arc<dyn Hittable>>;
+
+fn load(args: &SceneArgs) -> Result<(SceneFile, Scene, SceneBvh), Box<dyn Error>> {
+    if let Some(threads) = args.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()?;
     }
+
+    let file = args.load()?;
+    let mut scene = file.build()?;
+    let objects = std::mem::take(&mut scene.world.objects);
+
+    Ok((file, scene, Bvh::new(objects)))
+}
+
+fn print_progress(done: usize, total: usize) {
+    print!("\rRendered {done}/{total} tiles");
+    std::io::stdout().flush().ok();
 }
 
-fn random_world() -> World {
-    let ground_material = Material::Lambetarian {
-        albedo: Colour::new(0.5, 0.5, 0.5),
-    };
-
-    let mut shapes = vec![Sphere {
-        center: Vector3::new(0.0, -1000.0, 0.0),
-        radius: 1000.0,
-        material: ground_material,
-    }];
-
-    let mut rng = rand::thread_rng();
-
-    for a in -11..11 {
-        for b in -11..11 {
-            let center = Vector3::new(
-                a as f64 + 0.9 * rng.gen::<f64>(),
-                0.2,
-                b as f64 + 0.9 * rng.gen::<f64>(),
+fn run(cli: Cli) -> Result<(), Box<dyn Error>> {
+    match cli.command {
+        Command::Render {
+            scene,
+            output,
+            format,
+        } => {
+            let format = match format {
+                Some(format) => format.into(),
+                None => ImageFormat::from_extension(&output).ok_or(
+                    "cannot tell the image format from the output extension, use --format",
+                )?,
+            };
+            let (_, scene, world) = load(&scene)?;
+            let camera = &scene.camera;
+
+            let now = Instant::now();
+            let image = render(
+                camera,
+                &world,
+                &scene.lights,
+                &scene.settings,
+                &print_progress,
             );
+            println!("\nRendering took {:.2?}", now.elapsed());
+
+            let layers: Vec<(&str, &Framebuffer)> = image
+                .aovs
+                .iter()
+                .map(|(aov, framebuffer)| (aov.name(), framebuffer))
+                .collect();
+            write_layers(&output, format, &image.beauty, &layers, &scene.display)?;
+        }
+        Command::Info { scene, save } => {
+            let (file, scene, world) = load(&scene)?;
+            if let Some(path) = save {
+                file.save(path)?;
+            }
 
-            if (center - Vector3::<f64>::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
-                let choose_mat: f64 = rng.gen();
-                let material = if choose_mat < 0.8 {
-                    Material::Lambetarian {
-                        albedo: Colour::random(),
-                    }
-                } else if choose_mat < 0.95 {
-                    Material::Metal {
-                        albedo: Colour::random(),
-                        fuzz: rng.gen_range(0.0..0.5),
-                    }
-                } else {
-                    Material::Dielectric {
-                        index_of_refraction: 1.5,
-                    }
-                };
-                shapes.push(Sphere {
-                    center,
-                    radius: 0.2,
-                    material,
-                });
+            let camera = &scene.camera;
+            let settings = &scene.settings;
+
+            println!("Resolution: {}x{}", camera.image_width, camera.image_height);
+            println!(
+                "Samples per pixel: {}, depth: {} to {}, seed: {}",
+                settings.samples_per_pixel, settings.min_depth, settings.max_depth, settings.seed
+            );
+            println!(
+                "Materials: {}, shapes: {}",
+                file.materials.len(),
+                file.shapes.len()
+            );
+            match world.bounding_box() {
+                Some(bounds) => {
+                    let (min, max): ([f64; 3], [f64; 3]) = (bounds.min.into(), bounds.max.into());
+                    println!("Bounds: {min:?} to {max:?}");
+                }
+                None => println!("Bounds: unbounded"),
             }
         }
-    }
-
-    shapes.push(Sphere {
-        center: Vector3::new(0.0, 1.0, 0.0),
-        radius: 1.0,
-        material: Material::Dielectric {
-            index_of_refraction: 1.5,
-        },
-    });
-
-    shapes.push(Sphere {
-        center: Vector3::new(-4.0, 1.0, 0.0),
-        radius: 1.0,
-        material: Material::Lambetarian {
-            albedo: Colour::new(0.4, 0.2, 0.1),
-        },
-    });
-
-    shapes.push(Sphere {
-        center: Vector3::new(4.0, 1.0, 0.0),
-        radius: 1.0,
-        material: Material::Metal {
-            albedo: Colour::new(0.7, 0.6, 0.5),
-            fuzz: 0.0,
-        },
-    });
-
-    World { shapes }
-}
+        Command::Bench { scene, iterations } => {
+            let (_, scene, world) = load(&scene)?;
+            let camera = &scene.camera;
+            let samples = camera.image_width as f64
+                * camera.image_height as f64
+                * scene.settings.samples_per_pixel as f64;
+
+            let mut timings = Vec::new();
+            for iteration in 1..=iterations {
+                let now = Instant::now();
+                render(
+                    camera,
+                    &world,
+                    &scene.lights,
+                    &scene.settings,
+                    &print_progress,
+                );
+                let elapsed = now.elapsed();
+                println!("\nIteration {iteration}: {elapsed:.2?}");
+                timings.push(elapsed);
+            }
 
-fn main() -> Result<(), Error> {
-    let look_from = Vector3::new(13.0, 2.0, 3.0);
-    let look_at = Vector3::new(0.0, 0.0, 0.0);
-
-    let camera = Camera::new(
-        look_from,
-        look_at,
-        Vector3::new(0.0, 1.0, 0.0),
-        20.0,
-        3.0 / 2.0,
-        0.1,
-        10.0,
-        1200,
-    );
-
-    let world = random_world();
-
-    let samples_per_pixel: u32 = 500;
-    let mut rng = rand::thread_rng();
-
-    let now = Instant::now();
-    let mut lines: Vec<String> = vec![format!(
-        "P3\n{} {}\n255",
-        camera.image_width, camera.image_height
-    )];
-    for j in (0..camera.image_height).rev() {
-        println!("Rendering scanline {j}");
-        for i in 0..camera.image_width {
-            let colour: Colour<f64> =
-                (0..samples_per_pixel).fold(Colour::new(0.0, 0.0, 0.0), |acc, _| {
-                    let u = (i as f64 + rng.gen::<f64>()) / (camera.image_width - 1) as f64;
-                    let v = (j as f64 + rng.gen::<f64>()) / (camera.image_height - 1) as f64;
-
-                    let ray = camera.get_ray(u, v);
-                    acc + ray_colour(&ray, &world, 50)
-                }) * (1.0 / samples_per_pixel as f64);
-
-            let mapped = Colour::<u32>::from(Colour::new(
-                colour.r.sqrt(),
-                colour.g.sqrt(),
-                colour.b.sqrt(),
-            ));
-            lines.push(format!("{} {} {}", mapped.r, mapped.g, mapped.b));
+            if let Some(best) = timings.iter().min() {
+                let mean = timings.iter().sum::<Duration>() / iterations;
+                println!(
+                    "Best {best:.2?}, mean {mean:.2?}, {:.2} Msamples/s",
+                    samples / best.as_secs_f64() / 1e6
+                );
+            }
         }
     }
-    println!("Rendering took {:.2?}", now.elapsed());
-
-    let mut output = File::create("output.ppm")?;
-    writeln!(output, "{}", lines.join("\n"))?;
 
     Ok(())
 }
+
+fn main() {
+    if let Err(error) = run(Cli::parse()) {
+        eprintln!("error: {error}");
+        std::process::exit(1);
+    }
+}
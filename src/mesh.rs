@@ -0,0 +1,335 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::{
+    aabb::Aabb,
+    bvh::Bvh,
+    ray::Ray,
+    sampler::Sampler,
+    shapes::{HitRecord, Hittable, Material, Tangents},
+};
+
+pub struct MeshData {
+    pub positions: Vec<Vector3<f64>>,
+    pub normals: Vec<Vector3<f64>>,
+    pub uvs: Vec<Vector2<f64>>,
+    pub indices: Vec<[usize; 3]>,
+}
+
+pub struct Triangle {
+    mesh: Arc<MeshData>,
+    index: usize,
+    pub material: Arc<Material>,
+}
+
+fn max_dimension(v: Vector3<f64>) -> usize {
+    if v.x > v.y && v.x > v.z {
+        0
+    } else if v.y > v.z {
+        1
+    } else {
+        2
+    }
+}
+
+// A uniformly distributed point on the triangle `p`.
+fn sample_triangle([p0, p1, p2]: [Vector3<f64>; 3], (s, t): (f64, f64)) -> Vector3<f64> {
+    let root = s.sqrt();
+    (1.0 - root) * p0 + root * (1.0 - t) * p1 + root * t * p2
+}
+
+fn triangle_area([p0, p1, p2]: [Vector3<f64>; 3]) -> f64 {
+    0.5 * (p1 - p0).cross(p2 - p0).magnitude()
+}
+
+// The solid angle density of a point picked with density `1 / area` over a
+// surface, seen along `direction` where it is hit.
+fn solid_angle_pdf(hit: &HitRecord, direction: Vector3<f64>, area: f64) -> f64 {
+    let distance_squared = hit.t * hit.t * direction.magnitude2();
+    let cosine = direction.dot(hit.normal).abs() / direction.magnitude();
+    distance_squared / (cosine * area)
+}
+
+impl Triangle {
+    pub fn new(mesh: Arc<MeshData>, index: usize, material: Arc<Material>) -> Self {
+        Triangle {
+            mesh,
+            index,
+            material,
+        }
+    }
+
+    pub fn vertices(&self) -> [Vector3<f64>; 3] {
+        let [a, b, c] = self.mesh.indices[self.index];
+        [
+            self.mesh.positions[a],
+            self.mesh.positions[b],
+            self.mesh.positions[c],
+        ]
+    }
+
+    // The normal changes as the vertex normals are interpolated, turned to
+    // the side of the geometric normal as the shading normal is. Texture
+    // coordinates that do not span the triangle give no tangents.
+    fn tangents(&self, geometric_normal: Vector3<f64>, (b0, b1, b2): (f64, f64, f64)) -> Tangents {
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+        let [p0, p1, p2] = self.vertices();
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+
+        let [n0, n1, n2] = if self.mesh.normals.is_empty() {
+            [zero; 3]
+        } else {
+            let normals = [
+                self.mesh.normals[i0],
+                self.mesh.normals[i1],
+                self.mesh.normals[i2],
+            ];
+            let interpolated = b0 * normals[0] + b1 * normals[1] + b2 * normals[2];
+            if interpolated.dot(geometric_normal) < 0.0 {
+                normals.map(|n| -n)
+            } else {
+                normals
+            }
+        };
+
+        if self.mesh.uvs.is_empty() {
+            return Tangents {
+                dpdu: p1 - p0,
+                dpdv: p2 - p0,
+                dndu: n1 - n0,
+                dndv: n2 - n0,
+            };
+        }
+
+        let duv02 = self.mesh.uvs[i0] - self.mesh.uvs[i2];
+        let duv12 = self.mesh.uvs[i1] - self.mesh.uvs[i2];
+        let det = duv02.x * duv12.y - duv02.y * duv12.x;
+        if det.abs() < 1e-12 {
+            return Tangents::flat(zero, zero);
+        }
+
+        let d_du = |d02: Vector3<f64>, d12: Vector3<f64>| (duv12.y * d02 - duv02.y * d12) / det;
+        let d_dv = |d02: Vector3<f64>, d12: Vector3<f64>| (duv02.x * d12 - duv12.x * d02) / det;
+        Tangents {
+            dpdu: d_du(p0 - p2, p1 - p2),
+            dpdv: d_dv(p0 - p2, p1 - p2),
+            dndu: d_du(n0 - n2, n1 - n2),
+            dndv: d_dv(n0 - n2, n1 - n2),
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    // Watertight ray/triangle intersection (Woop, Benthin and Wald 2013): the
+    // triangle is sheared into ray space so that shared edges are tested with
+    // identical arithmetic and rays cannot slip between neighbouring faces.
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let [p0, p1, p2] = self.vertices();
+        let d = ray.direction;
+
+        let kz = max_dimension(Vector3::new(d.x.abs(), d.y.abs(), d.z.abs()));
+        let mut kx = (kz + 1) % 3;
+        let mut ky = (kx + 1) % 3;
+        if d[kz] < 0.0 {
+            std::mem::swap(&mut kx, &mut ky);
+        }
+
+        let sx = d[kx] / d[kz];
+        let sy = d[ky] / d[kz];
+        let sz = 1.0 / d[kz];
+
+        let a = p0 - ray.origin;
+        let b = p1 - ray.origin;
+        let c = p2 - ray.origin;
+
+        let ax = a[kx] - sx * a[kz];
+        let ay = a[ky] - sy * a[kz];
+        let bx = b[kx] - sx * b[kz];
+        let by = b[ky] - sy * b[kz];
+        let cx = c[kx] - sx * c[kz];
+        let cy = c[ky] - sy * c[kz];
+
+        let u = cx * by - cy * bx;
+        let v = ax * cy - ay * cx;
+        let w = bx * ay - by * ax;
+
+        if (u < 0.0 || v < 0.0 || w < 0.0) && (u > 0.0 || v > 0.0 || w > 0.0) {
+            return None;
+        }
+
+        let det = u + v + w;
+        if det == 0.0 {
+            return None;
+        }
+
+        let t = (u * sz * a[kz] + v * sz * b[kz] + w * sz * c[kz]) / det;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let (b0, b1, b2) = (u / det, v / det, w / det);
+        let [i0, i1, i2] = self.mesh.indices[self.index];
+
+        let uv = if self.mesh.uvs.is_empty() {
+            Vector2::new(b1, b2)
+        } else {
+            b0 * self.mesh.uvs[i0] + b1 * self.mesh.uvs[i1] + b2 * self.mesh.uvs[i2]
+        };
+
+        let geometric_normal = (p1 - p0).cross(p2 - p0).normalize();
+        let mut record = HitRecord::new(
+            t,
+            b0 * p0 + b1 * p1 + b2 * p2,
+            geometric_normal,
+            uv,
+            ray,
+            &self.material,
+        )
+        .with_footprint(ray, || self.tangents(geometric_normal, (b0, b1, b2)));
+
+        if !self.mesh.normals.is_empty() {
+            let shading_normal = (b0 * self.mesh.normals[i0]
+                + b1 * self.mesh.normals[i1]
+                + b2 * self.mesh.normals[i2])
+                .normalize();
+
+            record.normal = if shading_normal.dot(record.normal) < 0.0 {
+                -shading_normal
+            } else {
+                shading_normal
+            };
+        }
+
+        record.primitive = self.index;
+        Some(record.with_bump())
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(Aabb::from_points(self.vertices()).padded())
+    }
+
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        match self.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY) {
+            Some(hit) => solid_angle_pdf(&hit, direction, triangle_area(self.vertices())),
+            None => 0.0,
+        }
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        sample_triangle(self.vertices(), sampler.get_2d()) - origin
+    }
+}
+
+pub struct Mesh {
+    triangles: Bvh<Triangle>,
+    data: Arc<MeshData>,
+    material: Arc<Material>,
+    // The running total of the triangle areas, by index, so that sampling
+    // the mesh as a light picks triangles in proportion to their area.
+    areas: Vec<f64>,
+}
+
+impl Mesh {
+    pub fn new(data: MeshData, material: Material) -> Self {
+        let data = Arc::new(data);
+        let material = Arc::new(material);
+        let triangles: Vec<_> = (0..data.indices.len())
+            .map(|index| Triangle::new(data.clone(), index, material.clone()))
+            .collect();
+        let areas = triangles
+            .iter()
+            .scan(0.0, |total, triangle| {
+                *total += triangle_area(triangle.vertices());
+                Some(*total)
+            })
+            .collect();
+
+        Mesh {
+            triangles: Bvh::new(triangles),
+            data,
+            material,
+            areas,
+        }
+    }
+
+    pub fn material(&self) -> &Material {
+        &self.material
+    }
+
+    fn area(&self) -> f64 {
+        self.areas.last().copied().unwrap_or(0.0)
+    }
+}
+
+impl Hittable for Mesh {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        self.triangles.hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.triangles.bounding_box()
+    }
+
+    // Every point of the mesh is equally likely, so a direction is as likely
+    // as the surface it crosses, summed over each time it crosses the mesh.
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let area = self.area();
+        if area <= 0.0 {
+            return 0.0;
+        }
+
+        let ray = Ray::new(origin, direction);
+        let mut t_min = 0.001;
+        let mut pdf = 0.0;
+        while let Some(hit) = self.triangles.hit(&ray, t_min, f64::INFINITY) {
+            pdf += solid_angle_pdf(&hit, direction, area);
+            t_min = hit.t + 1e-9 * hit.t.max(1.0);
+        }
+        pdf
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let u = sampler.get_1d() * self.area();
+        let index = self
+            .areas
+            .partition_point(|&total| total <= u)
+            .min(self.areas.len().saturating_sub(1));
+        let Some(&[a, b, c]) = self.data.indices.get(index) else {
+            return Vector3::unit_x();
+        };
+
+        let positions = &self.data.positions;
+        sample_triangle([positions[a], positions[b], positions[c]], sampler.get_2d()) - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::shapes::assert_pdf_normalised;
+
+    // A tetrahedron is closed, so directions that cross it do so twice and
+    // the density must count both to integrate to one over the sphere.
+    #[test]
+    fn mesh_pdf_integrates_to_one() {
+        let mesh = Mesh::new(
+            MeshData {
+                positions: vec![
+                    Vector3::new(1.0, 1.0, 1.0),
+                    Vector3::new(1.0, -1.0, -1.0),
+                    Vector3::new(-1.0, 1.0, -1.0),
+                    Vector3::new(-1.0, -1.0, 1.0),
+                ],
+                normals: Vec::new(),
+                uvs: Vec::new(),
+                indices: vec![[0, 1, 2], [0, 3, 1], [0, 2, 3], [1, 3, 2]],
+            },
+            Material::Lambetarian {
+                albedo: 0.5.into(),
+                bump: None,
+            },
+        );
+        assert_pdf_normalised(&mesh, Vector3::new(0.5, 0.3, 2.5));
+    }
+}
@@ -0,0 +1,244 @@
+use std::f64::consts::PI;
+
+use cgmath::{InnerSpace, Vector3};
+
+use crate::render::splitmix64;
+
+fn fade(t: f64) -> f64 {
+    t * t * t * (t * (t * 6.0 - 15.0) + 10.0)
+}
+
+fn lerp(t: f64, a: f64, b: f64) -> f64 {
+    a + t * (b - a)
+}
+
+fn lattice_hash(x: i64, y: i64, z: i64) -> u64 {
+    splitmix64(x as u64 ^ splitmix64(y as u64 ^ splitmix64(z as u64)))
+}
+
+// The dot product of the offset (x, y, z) with one of the twelve edge
+// directions of a cube, picked by the hash as in Perlin's improved noise.
+fn gradient(hash: u64, x: f64, y: f64, z: f64) -> f64 {
+    let h = hash & 15;
+    let u = if h < 8 { x } else { y };
+    let v = match h {
+        0..=3 => y,
+        12 | 14 => x,
+        _ => z,
+    };
+    (if h & 1 == 0 { u } else { -u }) + (if h & 2 == 0 { v } else { -v })
+}
+
+// Perlin gradient noise in about [-1, 1], zero on the integer lattice. The
+// gradients are picked by hashing the lattice points rather than from a
+// permutation table, so the noise does not repeat.
+pub fn perlin(p: Vector3<f64>) -> f64 {
+    let (fx, fy, fz) = (p.x.floor(), p.y.floor(), p.z.floor());
+    let (x, y, z) = (p.x - fx, p.y - fy, p.z - fz);
+    let (ix, iy, iz) = (fx as i64, fy as i64, fz as i64);
+    let (u, v, w) = (fade(x), fade(y), fade(z));
+
+    let corner = |dx: i64, dy: i64, dz: i64| {
+        let hash = lattice_hash(ix + dx, iy + dy, iz + dz);
+        gradient(hash, x - dx as f64, y - dy as f64, z - dz as f64)
+    };
+
+    lerp(
+        w,
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 0), corner(1, 0, 0)),
+            lerp(u, corner(0, 1, 0), corner(1, 1, 0)),
+        ),
+        lerp(
+            v,
+            lerp(u, corner(0, 0, 1), corner(1, 0, 1)),
+            lerp(u, corner(0, 1, 1), corner(1, 1, 1)),
+        ),
+    )
+}
+
+// How much of the last, partial octave of `octaves` to add, faded in so that
+// the noise changes smoothly as the octave count does.
+fn partial_octave(octaves: f64) -> f64 {
+    let t = ((octaves - octaves.floor() - 0.3) / 0.4).clamp(0.0, 1.0);
+    t * t * (3.0 - 2.0 * t)
+}
+
+// Fractional Brownian motion: `octaves` layers of noise, each at twice the
+// frequency and half the amplitude of the last. A fractional count fades the
+// last octave in, which lets textures drop octaves finer than a pixel without
+// popping.
+pub fn fbm(p: Vector3<f64>, octaves: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut point = p;
+    let mut weight = 1.0;
+    for _ in 0..octaves.max(0.0) as u32 {
+        sum += weight * perlin(point);
+        weight *= 0.5;
+        point *= 2.0;
+    }
+
+    let partial = partial_octave(octaves);
+    if partial > 0.0 {
+        sum += partial * weight * perlin(point);
+    }
+    sum
+}
+
+// Like `fbm`, but summing the magnitude of each octave, which gives creases
+// where the noise crosses zero.
+pub fn turbulence(p: Vector3<f64>, octaves: f64) -> f64 {
+    let mut sum = 0.0;
+    let mut point = p;
+    let mut weight = 1.0;
+    for _ in 0..octaves.max(0.0) as u32 {
+        sum += weight * perlin(point).abs();
+        weight *= 0.5;
+        point *= 2.0;
+    }
+
+    let partial = partial_octave(octaves);
+    if partial > 0.0 {
+        sum += partial * weight * perlin(point).abs();
+    }
+    sum
+}
+
+// The distances from a point to the nearest and second nearest of a set of
+// feature points, one jittered into each unit cell, and a hash of the cell
+// holding the nearest, which is the same for every point in its Voronoi cell.
+#[derive(Debug, Clone, Copy)]
+pub struct Worley {
+    pub nearest: f64,
+    pub second: f64,
+    pub cell: u64,
+}
+
+// Worley's cellular noise.
+pub fn worley(p: Vector3<f64>) -> Worley {
+    let (ix, iy, iz) = (p.x.floor() as i64, p.y.floor() as i64, p.z.floor() as i64);
+    let unit = |bits: u64| (bits & 0x1f_ffff) as f64 / (1 << 21) as f64;
+
+    let mut result = Worley {
+        nearest: f64::INFINITY,
+        second: f64::INFINITY,
+        cell: 0,
+    };
+    for z in iz - 1..=iz + 1 {
+        for y in iy - 1..=iy + 1 {
+            for x in ix - 1..=ix + 1 {
+                let hash = splitmix64(lattice_hash(x, y, z));
+                let feature = Vector3::new(
+                    x as f64 + unit(hash),
+                    y as f64 + unit(hash >> 21),
+                    z as f64 + unit(hash >> 42),
+                );
+
+                let distance = (feature - p).magnitude();
+                if distance < result.nearest {
+                    result.second = result.nearest;
+                    result.nearest = distance;
+                    result.cell = hash;
+                } else if distance < result.second {
+                    result.second = distance;
+                }
+            }
+        }
+    }
+    result
+}
+
+// The octaves of noise at unit frequency worth adding for a footprint
+// `width` wide, since finer ones would only alias.
+pub fn octaves_for(width: f64, max_octaves: f64) -> f64 {
+    if width > 0.0 {
+        (-1.0 - width.log2()).clamp(0.0, max_octaves)
+    } else {
+        max_octaves
+    }
+}
+
+// Bands of marble a unit apart across x, pushed around by turbulence
+// `distortion` units strong, in [0, 1].
+pub fn marble(p: Vector3<f64>, distortion: f64, octaves: f64) -> f64 {
+    let phase = p.x + distortion * turbulence(p, octaves);
+    0.5 * (1.0 + (2.0 * PI * phase).sin())
+}
+
+// Growth rings a unit apart around the y axis, made irregular by noise
+// `distortion` units strong. Each ring darkens slowly from its inside and
+// ends sharply, like earlywood turning into latewood; the value is 0 at the
+// start of a ring and 1 at its end.
+pub fn wood(p: Vector3<f64>, distortion: f64, octaves: f64) -> f64 {
+    let radius = (p.x * p.x + p.z * p.z).sqrt();
+    // The grain is stretched along the trunk.
+    let grain = Vector3::new(p.x, 0.1 * p.y, p.z);
+    let ring = (radius + distortion * fbm(grain, octaves)).rem_euclid(1.0);
+    ring * ring
+}
+
+// Interlocking crystals about a unit across, each of its own shade with fine
+// speckles and darker seams between them, in [0, 1].
+pub fn granite(p: Vector3<f64>, octaves: f64) -> f64 {
+    let cells = worley(p);
+    let shade = (cells.cell >> 11) as f64 / (1u64 << 53) as f64;
+    let seam = ((cells.second - cells.nearest) / 0.05).min(1.0);
+    let speckle = fbm(8.0 * p, (octaves - 3.0).max(0.0));
+
+    ((0.2 + 0.6 * shade + 0.3 * speckle) * (0.6 + 0.4 * seam)).clamp(0.0, 1.0)
+}
+
+#[cfg(test)]
+mod tests {
+    use rand::{rngs::StdRng, Rng, SeedableRng};
+
+    use super::*;
+
+    fn random_point(rng: &mut StdRng, extent: f64) -> Vector3<f64> {
+        Vector3::new(
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+            rng.gen_range(-extent..extent),
+        )
+    }
+
+    #[test]
+    fn perlin_is_zero_on_the_lattice_and_bounded() {
+        for x in -3..=3 {
+            for y in -3..=3 {
+                for z in -3..=3 {
+                    let p = Vector3::new(x as f64, y as f64, z as f64);
+                    assert_eq!(perlin(p), 0.0, "{p:?}");
+                }
+            }
+        }
+
+        let mut rng = StdRng::seed_from_u64(1);
+        let mut largest: f64 = 0.0;
+        for _ in 0..100_000 {
+            largest = largest.max(perlin(random_point(&mut rng, 50.0)).abs());
+        }
+        assert!(largest <= 1.1, "{largest}");
+        assert!(largest > 0.5, "{largest}");
+    }
+
+    // Moving less than half the gap between the nearest and second nearest
+    // feature points cannot change which one is nearest.
+    #[test]
+    fn worley_cells_are_constant_around_their_feature_points() {
+        let mut rng = StdRng::seed_from_u64(2);
+        for _ in 0..10_000 {
+            let p = random_point(&mut rng, 20.0);
+            let noise = worley(p);
+            assert!(noise.nearest <= noise.second, "{noise:?}");
+
+            let reach = 0.45 * (noise.second - noise.nearest);
+            let offset = random_point(&mut rng, 1.0);
+            if offset.magnitude() > 1e-9 {
+                let q = p + offset.normalize() * reach * rng.gen::<f64>();
+                assert_eq!(worley(q).cell, noise.cell, "{p:?} and {q:?}");
+            }
+        }
+    }
+}
@@ -0,0 +1,152 @@
+use std::{fmt, io, path::Path};
+
+use cgmath::{Vector2, Vector3};
+
+use crate::{
+    colour::Colour,
+    mesh::{Mesh, MeshData},
+    shapes::Material,
+    texture::{ImageCache, ImageFilter, Texture, WrapMode},
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Load(tobj::LoadError),
+    Texture { path: String, source: io::Error },
+}
+
+impl fmt::Display for ObjError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Load(source) => write!(f, "{source}"),
+            ObjError::Texture { path, source } => write!(f, "{path}: {source}"),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+impl From<tobj::LoadError> for ObjError {
+    fn from(error: tobj::LoadError) -> Self {
+        ObjError::Load(error)
+    }
+}
+
+fn colour(rgb: [f32; 3]) -> Colour<f64> {
+    Colour::new(rgb[0] as f64, rgb[1] as f64, rgb[2] as f64)
+}
+
+// Illumination models 4, 6, 7 and 9 describe refractive surfaces and 3 and 5
+// reflective ones; everything else is treated as diffuse, with the colour
+// from `map_Kd` if there is one, relative to `dir`.
+fn material_from_mtl(
+    mtl: &tobj::Material,
+    dir: &Path,
+    images: &ImageCache,
+) -> Result<Material, ObjError> {
+    let refractive = matches!(mtl.illumination_model, Some(4 | 6 | 7 | 9))
+        || mtl.dissolve.is_some_and(|d| d < 1.0);
+    let reflective = matches!(mtl.illumination_model, Some(3 | 5));
+    let emissive = mtl.emissive.filter(|ke| ke.iter().any(|&v| v > 0.0));
+
+    Ok(if let Some(ke) = emissive {
+        Material::DiffuseLight {
+            emit: colour(ke).into(),
+        }
+    } else if refractive {
+        Material::Dielectric {
+            index_of_refraction: (mtl.optical_density.unwrap_or(1.5) as f64).into(),
+            bump: None,
+        }
+    } else if reflective {
+        Material::Metal {
+            albedo: colour(mtl.specular.or(mtl.diffuse).unwrap_or([0.8; 3])).into(),
+            fuzz: mtl
+                .shininess
+                .map_or(0.0, |ns| (2.0 / (ns as f64 + 2.0)).sqrt())
+                .into(),
+            bump: None,
+        }
+    } else if let Some(map) = &mtl.diffuse_texture {
+        let image = images
+            .open(&dir.join(map), true)
+            .map_err(|source| ObjError::Texture {
+                path: map.clone(),
+                source,
+            })?;
+        Material::Lambetarian {
+            albedo: Texture::Image {
+                image,
+                filter: ImageFilter::default(),
+                wrap: WrapMode::default(),
+            },
+            bump: None,
+        }
+    } else {
+        Material::Lambetarian {
+            albedo: colour(mtl.diffuse.unwrap_or([0.8; 3])).into(),
+            bump: None,
+        }
+    })
+}
+
+fn mesh_data(mesh: &tobj::Mesh) -> MeshData {
+    let positions = mesh
+        .positions
+        .chunks_exact(3)
+        .map(|p| Vector3::new(p[0] as f64, p[1] as f64, p[2] as f64))
+        .collect();
+    let normals = mesh
+        .normals
+        .chunks_exact(3)
+        .map(|n| Vector3::new(n[0] as f64, n[1] as f64, n[2] as f64))
+        .collect();
+    let uvs = mesh
+        .texcoords
+        .chunks_exact(2)
+        .map(|uv| Vector2::new(uv[0] as f64, uv[1] as f64))
+        .collect();
+    let indices = mesh
+        .indices
+        .chunks_exact(3)
+        .map(|i| [i[0] as usize, i[1] as usize, i[2] as usize])
+        .collect();
+
+    MeshData {
+        positions,
+        normals,
+        uvs,
+        indices,
+    }
+}
+
+pub fn load_obj<P: AsRef<Path>>(
+    path: P,
+    default_material: Material,
+    images: &ImageCache,
+) -> Result<Vec<Mesh>, ObjError> {
+    let path = path.as_ref();
+    let dir = path.parent().unwrap_or(Path::new("."));
+    let (models, materials) = tobj::load_obj(path, &tobj::GPU_LOAD_OPTIONS)?;
+    // An MTL file that is missing or unreadable leaves every model with the
+    // default material, as models without a material already get, rather
+    // than failing the whole mesh.
+    let materials = materials
+        .unwrap_or_default()
+        .iter()
+        .map(|mtl| material_from_mtl(mtl, dir, images))
+        .collect::<Result<Vec<_>, _>>()?;
+
+    Ok(models
+        .iter()
+        .map(|model| {
+            let material = model
+                .mesh
+                .material_id
+                .and_then(|id| materials.get(id).cloned())
+                .unwrap_or_else(|| default_material.clone());
+
+            Mesh::new(mesh_data(&model.mesh), material)
+        })
+        .collect())
+}
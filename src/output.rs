@@ -0,0 +1,442 @@
+use std::{
+    fs::File,
+    io::{self, BufWriter, Write},
+    path::{Path, PathBuf},
+};
+
+use crate::{colour::Colour, tonemap::DisplayTransform};
+
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Colour<f64>>,
+}
+
+impl Framebuffer {
+    pub fn new(width: u32, height: u32) -> Self {
+        Framebuffer {
+            width,
+            height,
+            pixels: vec![Colour::new(0.0, 0.0, 0.0); (width * height) as usize],
+        }
+    }
+
+    pub fn get(&self, x: u32, y: u32) -> Colour<f64> {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    pub fn set(&mut self, x: u32, y: u32, colour: Colour<f64>) {
+        self.pixels[(y * self.width + x) as usize] = colour;
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ImageFormat {
+    Png8,
+    Png16,
+    Ppm,
+    Pfm,
+    ExrHalf,
+    ExrFloat,
+    Hdr,
+}
+
+impl ImageFormat {
+    pub fn from_extension(path: &Path) -> Option<ImageFormat> {
+        let extension = path.extension()?.to_str()?.to_ascii_lowercase();
+        match extension.as_str() {
+            "png" => Some(ImageFormat::Png8),
+            "ppm" => Some(ImageFormat::Ppm),
+            "pfm" => Some(ImageFormat::Pfm),
+            "exr" => Some(ImageFormat::ExrHalf),
+            "hdr" => Some(ImageFormat::Hdr),
+            _ => None,
+        }
+    }
+}
+
+// The display encoders quantise values that the display transform has
+// already brought into [0, 1].
+fn encode_8bit(value: f64) -> u8 {
+    (256.0 * value.clamp(0.0, 0.999)) as u8
+}
+
+fn encode_16bit(value: f64) -> u16 {
+    (65535.0 * value.clamp(0.0, 1.0)).round() as u16
+}
+
+fn write_png<W: Write>(
+    writer: W,
+    framebuffer: &Framebuffer,
+    display: &DisplayTransform,
+    sixteen_bit: bool,
+) -> io::Result<()> {
+    let mut encoder = png::Encoder::new(writer, framebuffer.width, framebuffer.height);
+    encoder.set_color(png::ColorType::Rgb);
+    encoder.set_source_srgb(png::SrgbRenderingIntent::Perceptual);
+
+    let pixel_map = display.pixel_map();
+    let pixels = framebuffer.pixels.iter().map(|&c| pixel_map(c));
+    let data: Vec<u8> = if sixteen_bit {
+        encoder.set_depth(png::BitDepth::Sixteen);
+        pixels
+            .flat_map(|c| [c.r, c.g, c.b])
+            .flat_map(|v| encode_16bit(v).to_be_bytes())
+            .collect()
+    } else {
+        encoder.set_depth(png::BitDepth::Eight);
+        pixels
+            .flat_map(|c| [encode_8bit(c.r), encode_8bit(c.g), encode_8bit(c.b)])
+            .collect()
+    };
+
+    let mut writer = encoder.write_header()?;
+    writer.write_image_data(&data)?;
+    Ok(())
+}
+
+fn write_ppm<W: Write>(
+    mut writer: W,
+    framebuffer: &Framebuffer,
+    display: &DisplayTransform,
+) -> io::Result<()> {
+    write!(
+        writer,
+        "P6\n{} {}\n255\n",
+        framebuffer.width, framebuffer.height
+    )?;
+
+    let pixel_map = display.pixel_map();
+    let data: Vec<u8> = framebuffer
+        .pixels
+        .iter()
+        .map(|&c| pixel_map(c))
+        .flat_map(|c| [encode_8bit(c.r), encode_8bit(c.g), encode_8bit(c.b)])
+        .collect();
+    writer.write_all(&data)
+}
+
+// PFM stores linear little-endian floats with the bottom row first.
+fn write_pfm<W: Write>(mut writer: W, framebuffer: &Framebuffer) -> io::Result<()> {
+    write!(
+        writer,
+        "PF\n{} {}\n-1.0\n",
+        framebuffer.width, framebuffer.height
+    )?;
+
+    for row in framebuffer.pixels.chunks(framebuffer.width as usize).rev() {
+        for c in row {
+            for v in [c.r, c.g, c.b] {
+                writer.write_all(&(v as f32).to_le_bytes())?;
+            }
+        }
+    }
+    Ok(())
+}
+
+// Each layer becomes an R, G and B channel triple in a single-part EXR; layers
+// other than an unnamed beauty layer get their name as a channel prefix, e.g.
+// `albedo.R`.
+pub fn write_exr_layers(
+    path: &Path,
+    layers: &[(&str, &Framebuffer)],
+    precision: ExrPrecision,
+) -> io::Result<()> {
+    use exr::prelude::*;
+
+    let Some((_, first)) = layers.first() else {
+        return Err(io::Error::new(
+            io::ErrorKind::InvalidInput,
+            "an EXR image needs at least one layer",
+        ));
+    };
+    let size = Vec2(first.width as usize, first.height as usize);
+
+    let mut channels = Vec::new();
+    for (name, framebuffer) in layers {
+        for (component, index) in [("R", 0), ("G", 1), ("B", 2)] {
+            let values = framebuffer.pixels.iter().map(|c| [c.r, c.g, c.b][index]);
+            let samples = match precision {
+                ExrPrecision::Half => FlatSamples::F16(values.map(f16::from_f64).collect()),
+                ExrPrecision::Float => FlatSamples::F32(values.map(|v| v as f32).collect()),
+            };
+            let channel_name = if name.is_empty() {
+                component.to_string()
+            } else {
+                format!("{name}.{component}")
+            };
+            channels.push(AnyChannel::new(channel_name.as_str(), samples));
+        }
+    }
+
+    let layer = Layer::new(
+        size,
+        LayerAttributes::default(),
+        Encoding::SMALL_LOSSLESS,
+        AnyChannels::sort(channels.into()),
+    );
+
+    Image::from_layer(layer)
+        .write()
+        .to_file(path)
+        .map_err(|e| match e {
+            exr::error::Error::Io(e) => e,
+            e => io::Error::other(e),
+        })
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum ExrPrecision {
+    Half,
+    Float,
+}
+
+fn rgbe(c: &Colour<f64>) -> [u8; 4] {
+    let v = c.r.max(c.g).max(c.b);
+    if v < 1e-32 {
+        return [0; 4];
+    }
+
+    // v = m * 2^e with m in [0.5, 1), as C's frexp.
+    let e = v.log2().floor() as i32 + 1;
+    let scale = 256.0 * 2f64.powi(-e);
+    [
+        (c.r.max(0.0) * scale) as u8,
+        (c.g.max(0.0) * scale) as u8,
+        (c.b.max(0.0) * scale) as u8,
+        (e + 128) as u8,
+    ]
+}
+
+// Radiance RGBE with flat (not run-length encoded) scanlines, top row first.
+fn write_hdr<W: Write>(mut writer: W, framebuffer: &Framebuffer) -> io::Result<()> {
+    write!(
+        writer,
+        "#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y {} +X {}\n",
+        framebuffer.height, framebuffer.width
+    )?;
+
+    let data: Vec<u8> = framebuffer.pixels.iter().flat_map(rgbe).collect();
+    writer.write_all(&data)
+}
+
+// Linear formats store the framebuffer as it is, while the 8 and 16-bit ones
+// pass it through `display` first.
+pub fn write_image(
+    path: &Path,
+    format: ImageFormat,
+    framebuffer: &Framebuffer,
+    display: &DisplayTransform,
+) -> io::Result<()> {
+    match format {
+        ImageFormat::ExrHalf => {
+            return write_exr_layers(path, &[("", framebuffer)], ExrPrecision::Half)
+        }
+        ImageFormat::ExrFloat => {
+            return write_exr_layers(path, &[("", framebuffer)], ExrPrecision::Float)
+        }
+        _ => {}
+    }
+
+    let mut writer = BufWriter::new(File::create(path)?);
+
+    match format {
+        ImageFormat::Png8 => write_png(&mut writer, framebuffer, display, false)?,
+        ImageFormat::Png16 => write_png(&mut writer, framebuffer, display, true)?,
+        ImageFormat::Ppm => write_ppm(&mut writer, framebuffer, display)?,
+        ImageFormat::Pfm => write_pfm(&mut writer, framebuffer)?,
+        ImageFormat::Hdr => write_hdr(&mut writer, framebuffer)?,
+        ImageFormat::ExrHalf | ImageFormat::ExrFloat => unreachable!(),
+    }
+
+    writer.flush()
+}
+
+// `out.png` becomes `out.albedo.png` for the albedo layer.
+fn sibling_path(path: &Path, layer: &str) -> PathBuf {
+    let stem = path.file_stem().unwrap_or_default().to_string_lossy();
+    let mut name = format!("{stem}.{layer}");
+    if let Some(extension) = path.extension() {
+        name = format!("{name}.{}", extension.to_string_lossy());
+    }
+    path.with_file_name(name)
+}
+
+// Writes the beauty image to `path` along with named extra layers, which go
+// into the same file for EXR and into sibling files for every other format.
+// Only the beauty image goes through `display`; the layers hold data rather
+// than pictures and are just sRGB encoded.
+pub fn write_layers(
+    path: &Path,
+    format: ImageFormat,
+    beauty: &Framebuffer,
+    layers: &[(&str, &Framebuffer)],
+    display: &DisplayTransform,
+) -> io::Result<()> {
+    let precision = match format {
+        ImageFormat::ExrHalf => Some(ExrPrecision::Half),
+        ImageFormat::ExrFloat => Some(ExrPrecision::Float),
+        _ => None,
+    };
+
+    if let Some(precision) = precision {
+        let mut all = vec![("", beauty)];
+        all.extend_from_slice(layers);
+        return write_exr_layers(path, &all, precision);
+    }
+
+    write_image(path, format, beauty, display)?;
+    for (name, framebuffer) in layers {
+        let path = sibling_path(path, name);
+        write_image(&path, format, framebuffer, &DisplayTransform::default())?;
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::input::read_image;
+
+    // A gradient that is different in every pixel and channel, with the top
+    // left pixel darkest.
+    fn gradient(width: u32, height: u32) -> Framebuffer {
+        let pixels = (0..width * height)
+            .map(|i| {
+                let (x, y) = ((i % width) as f64, (i / width) as f64);
+                let (w, h) = (width as f64, height as f64);
+                Colour::new(x / w, y / h, (x + y * w) / (w * h))
+            })
+            .collect();
+        Framebuffer {
+            width,
+            height,
+            pixels,
+        }
+    }
+
+    #[test]
+    fn png_round_trip() {
+        let image = gradient(5, 3);
+        for (format, tolerance) in [(ImageFormat::Png8, 0.01), (ImageFormat::Png16, 1e-4)] {
+            let path = std::env::temp_dir()
+                .join(format!("rust-tracer-{format:?}-{}.png", std::process::id()));
+            write_image(&path, format, &image, &DisplayTransform::default()).unwrap();
+            let read = read_image(&path, true).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!((read.width, read.height), (5, 3));
+            for (written, read) in image.pixels.iter().zip(&read.pixels) {
+                for (a, b) in [
+                    (written.r, read.r),
+                    (written.g, read.g),
+                    (written.b, read.b),
+                ] {
+                    assert!((a - b).abs() < tolerance, "{written:?} read as {read:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn ppm_is_binary_and_top_row_first() {
+        let mut image = Framebuffer::new(2, 2);
+        image.set(0, 0, Colour::new(1.0, 0.0, 0.0));
+        image.set(1, 1, Colour::new(0.0, 0.0, 1.0));
+
+        let mut bytes = Vec::new();
+        write_ppm(&mut bytes, &image, &DisplayTransform::default()).unwrap();
+
+        let header = b"P6\n2 2\n255\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(
+            &bytes[header.len()..],
+            [255, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 255]
+        );
+    }
+
+    #[test]
+    fn pfm_is_little_endian_and_bottom_row_first() {
+        let image = gradient(3, 2);
+        let mut bytes = Vec::new();
+        write_pfm(&mut bytes, &image).unwrap();
+
+        let header = b"PF\n3 2\n-1.0\n";
+        assert_eq!(&bytes[..header.len()], header);
+        let values: Vec<f32> = bytes[header.len()..]
+            .chunks_exact(4)
+            .map(|b| f32::from_le_bytes([b[0], b[1], b[2], b[3]]))
+            .collect();
+        assert_eq!(values.len(), 3 * 2 * 3);
+
+        for (i, pixel) in values.chunks_exact(3).enumerate() {
+            let (x, row) = (i as u32 % 3, i as u32 / 3);
+            let expected = image.get(x, 1 - row);
+            assert_eq!(
+                pixel,
+                [expected.r as f32, expected.g as f32, expected.b as f32]
+            );
+        }
+    }
+
+    // Radiance's header, then a flat scanline: black is all zeros and the
+    // channels share the exponent of the largest.
+    #[test]
+    fn hdr_header_and_rgbe_pixels() {
+        let image = Framebuffer {
+            width: 3,
+            height: 1,
+            pixels: vec![
+                Colour::new(0.0, 0.0, 0.0),
+                Colour::new(1.0, 1.0, 1.0),
+                Colour::new(0.5, 0.25, 3.0),
+            ],
+        };
+        let mut bytes = Vec::new();
+        write_hdr(&mut bytes, &image).unwrap();
+
+        let header = b"#?RADIANCE\nFORMAT=32-bit_rle_rgbe\n\n-Y 1 +X 3\n";
+        assert_eq!(&bytes[..header.len()], header);
+        assert_eq!(
+            &bytes[header.len()..],
+            [0, 0, 0, 0, 128, 128, 128, 129, 32, 16, 192, 130]
+        );
+    }
+
+    fn temp_exr(name: &str) -> PathBuf {
+        std::env::temp_dir().join(format!("rust-tracer-{name}-{}.exr", std::process::id()))
+    }
+
+    // Extra layers go into the same file under their own channel names and
+    // leave the beauty image in the plain R, G and B channels.
+    #[test]
+    fn exr_round_trip() {
+        let image = gradient(4, 3);
+        let albedo = Framebuffer::new(4, 3);
+        for (precision, tolerance) in [(ExrPrecision::Half, 1e-3), (ExrPrecision::Float, 1e-7)] {
+            let path = temp_exr(&format!("{precision:?}"));
+            write_exr_layers(&path, &[("", &image), ("albedo", &albedo)], precision).unwrap();
+            let read = read_image(&path, false).unwrap();
+            std::fs::remove_file(&path).unwrap();
+
+            assert_eq!((read.width, read.height), (4, 3));
+            for (written, read) in image.pixels.iter().zip(&read.pixels) {
+                for (a, b) in [
+                    (written.r, read.r),
+                    (written.g, read.g),
+                    (written.b, read.b),
+                ] {
+                    assert!((a - b).abs() < tolerance, "{written:?} read as {read:?}");
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn exr_without_layers_is_an_error() {
+        let path = temp_exr("empty");
+        let error = write_exr_layers(&path, &[], ExrPrecision::Half).unwrap_err();
+        assert_eq!(error.kind(), io::ErrorKind::InvalidInput);
+        assert!(!path.exists());
+    }
+}
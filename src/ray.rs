@@ -1,16 +1,44 @@
 use cgmath::Vector3;
 
+// Rays through the neighbouring pixels in x and y, which track how wide a
+// pixel's footprint has grown by the time a ray reaches a surface.
+#[derive(Debug, Clone, Copy)]
+pub struct Differentials {
+    pub rx_origin: Vector3<f64>,
+    pub rx_direction: Vector3<f64>,
+    pub ry_origin: Vector3<f64>,
+    pub ry_direction: Vector3<f64>,
+}
+
+#[derive(Debug, Clone, Copy)]
 pub struct Ray {
     pub origin: Vector3<f64>,
     pub direction: Vector3<f64>,
+    pub differentials: Option<Differentials>,
 }
 
 impl Ray {
     pub fn new(origin: Vector3<f64>, direction: Vector3<f64>) -> Self {
-        Self { origin, direction }
+        Self {
+            origin,
+            direction,
+            differentials: None,
+        }
     }
 
     pub fn at(&self, t: f64) -> Vector3<f64> {
         self.origin + t * self.direction
     }
+
+    // Brings the offset rays `scale` of the way towards this one, for when
+    // several samples share a pixel and each covers less of it.
+    pub fn scale_differentials(mut self, scale: f64) -> Self {
+        if let Some(d) = &mut self.differentials {
+            d.rx_origin = self.origin + (d.rx_origin - self.origin) * scale;
+            d.ry_origin = self.origin + (d.ry_origin - self.origin) * scale;
+            d.rx_direction = self.direction + (d.rx_direction - self.direction) * scale;
+            d.ry_direction = self.direction + (d.ry_direction - self.direction) * scale;
+        }
+        self
+    }
 }
@@ -0,0 +1,472 @@
+use std::sync::atomic::{AtomicUsize, Ordering};
+
+use cgmath::InnerSpace;
+use rand_pcg::Pcg32;
+use rayon::prelude::*;
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    adaptive::{AdaptiveSampling, Welford},
+    aov::{Aov, Aovs},
+    camera::Camera,
+    colour::Colour,
+    denoise::Denoiser,
+    filter::Filter,
+    integrator::{
+        AmbientOcclusion, DebugView, DirectLighting, Integrator, IntegratorKind, PathTracer,
+        Whitted,
+    },
+    output::Framebuffer,
+    ray::Ray,
+    sampler::SamplerKind,
+    shapes::{Hittable, World},
+};
+
+#[derive(Debug, Clone, Copy)]
+pub enum Background {
+    Sky,
+    Solid(Colour<f64>),
+}
+
+impl Background {
+    pub fn colour(&self, ray: &Ray) -> Colour<f64> {
+        match self {
+            Background::Sky => {
+                let unit_direction = ray.direction.normalize();
+                let t = 0.5 * (unit_direction.y + 1.0);
+                Colour::new(
+                    (1.0 - t) + t * 0.5,
+                    (1.0 - t) + t * 0.7,
+                    (1.0 - t) + t * 1.0,
+                )
+            }
+            Background::Solid(colour) => *colour,
+        }
+    }
+}
+
+// How light and BSDF samples of the same path are weighted against each other
+// in multiple importance sampling.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum Heuristic {
+    Balance,
+    Power,
+}
+
+impl Heuristic {
+    pub fn weight(self, pdf: f64, other_pdf: f64) -> f64 {
+        let (a, b) = match self {
+            Heuristic::Balance => (pdf, other_pdf),
+            Heuristic::Power => (pdf * pdf, other_pdf * other_pdf),
+        };
+        if a + b > 0.0 {
+            a / (a + b)
+        } else {
+            0.0
+        }
+    }
+}
+
+pub struct RenderOutput {
+    pub beauty: Framebuffer,
+    pub aovs: Vec<(Aov, Framebuffer)>,
+}
+
+pub struct RenderSettings {
+    pub samples_per_pixel: u32,
+    // Bounces before Russian roulette may end a path, and the hard limit.
+    pub min_depth: u32,
+    pub max_depth: u32,
+    pub tile_size: u32,
+    pub seed: u64,
+    pub background: Background,
+    pub heuristic: Heuristic,
+    pub integrator: IntegratorKind,
+    pub aovs: Vec<Aov>,
+    pub denoiser: Option<Denoiser>,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+}
+
+impl Default for RenderSettings {
+    fn default() -> Self {
+        RenderSettings {
+            samples_per_pixel: 500,
+            min_depth: 3,
+            max_depth: 50,
+            tile_size: 32,
+            seed: 0,
+            background: Background::Sky,
+            heuristic: Heuristic::Power,
+            integrator: IntegratorKind::Path,
+            aovs: Vec::new(),
+            denoiser: None,
+            adaptive: None,
+            sampler: SamplerKind::Sobol,
+            filter: Filter::default(),
+        }
+    }
+}
+
+#[derive(Debug, Clone, Copy)]
+struct Tile {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+}
+
+fn tiles(width: u32, height: u32, tile_size: u32) -> Vec<Tile> {
+    let mut tiles = Vec::new();
+    for y0 in (0..height).step_by(tile_size as usize) {
+        for x0 in (0..width).step_by(tile_size as usize) {
+            tiles.push(Tile {
+                x0,
+                y0,
+                x1: (x0 + tile_size).min(width),
+                y1: (y0 + tile_size).min(height),
+            });
+        }
+    }
+    tiles
+}
+
+pub(crate) fn splitmix64(mut x: u64) -> u64 {
+    x = x.wrapping_add(0x9e37_79b9_7f4a_7c15);
+    x = (x ^ (x >> 30)).wrapping_mul(0xbf58_476d_1ce4_e5b9);
+    x = (x ^ (x >> 27)).wrapping_mul(0x94d0_49bb_1331_11eb);
+    x ^ (x >> 31)
+}
+
+// Every sample of every pixel draws from its own PCG stream derived only from
+// the seed and its coordinates, so images are bit-identical no matter how the
+// tiles are scheduled across threads.
+pub fn sample_rng(seed: u64, pixel: u64, sample: u32) -> Pcg32 {
+    Pcg32::new(splitmix64(seed ^ splitmix64(pixel)), sample as u64)
+}
+
+// The weighted sums of the samples a tile splatted into the pixels its filter
+// reaches, which extend beyond the tile by the filter radius, for the beauty
+// image followed by each of the AOVs, and how many samples each pixel of the
+// tile itself took.
+struct Splats {
+    x0: u32,
+    y0: u32,
+    x1: u32,
+    y1: u32,
+    layers: Vec<Vec<Colour<f64>>>,
+    weights: Vec<f64>,
+    samples: Vec<u32>,
+}
+
+#[allow(clippy::too_many_arguments)]
+fn render_tile<T: Hittable, I: Integrator>(
+    camera: &Camera,
+    scene: &T,
+    lights: &World,
+    integrator: &I,
+    settings: &RenderSettings,
+    aovs: &[Aov],
+    tile: Tile,
+) -> Splats {
+    let (width, height) = (camera.image_width, camera.image_height);
+    let filter = settings.filter;
+    let radius = filter.radius();
+    let reach = radius.ceil() as u32;
+
+    let (x0, y0) = (tile.x0.saturating_sub(reach), tile.y0.saturating_sub(reach));
+    let (x1, y1) = ((tile.x1 + reach).min(width), (tile.y1 + reach).min(height));
+    // The first and last pixels along an axis whose centres lie within the
+    // filter radius of a sample at `f`, from those of the splats.
+    let first = |f: f64, lo: u32| ((f - 0.5 - radius).ceil().max(lo as f64)) as u32;
+    let last = |f: f64, hi: u32| ((f - 0.5 + radius).floor().min(hi as f64 - 1.0)) as u32;
+    let pixel_count = ((x1 - x0) * (y1 - y0)) as usize;
+    let mut splats = Splats {
+        x0,
+        y0,
+        x1,
+        y1,
+        layers: vec![vec![Colour::new(0.0, 0.0, 0.0); pixel_count]; 1 + aovs.len()],
+        weights: vec![0.0; pixel_count],
+        samples: Vec::with_capacity(((tile.x1 - tile.x0) * (tile.y1 - tile.y0)) as usize),
+    };
+
+    let mut sampler = settings
+        .sampler
+        .build(settings.samples_per_pixel, settings.seed);
+    let sampler = sampler.as_mut();
+    let mut x_weights = Vec::new();
+    // Neighbouring samples of a pixel are closer together than neighbouring
+    // pixels, so the differentials shrink as the sample count grows.
+    let differential_scale = (1.0 / (settings.samples_per_pixel as f64).sqrt()).max(0.125);
+
+    for y in tile.y0..tile.y1 {
+        // Image rows run top to bottom while the camera's t runs bottom to top.
+        let j = height - 1 - y;
+        for i in tile.x0..tile.x1 {
+            let pixel = (y * width + i) as u64;
+            let mut statistics = Welford::default();
+            let mut samples = 0;
+
+            while samples < settings.samples_per_pixel {
+                sampler.start_pixel_sample(pixel, samples);
+                samples += 1;
+
+                let (du, dv) = sampler.get_2d();
+                let u = (i as f64 + du) / (width.max(2) - 1) as f64;
+                let v = (j as f64 + dv) / (height.max(2) - 1) as f64;
+
+                let ray = camera
+                    .get_ray(u, v, sampler)
+                    .scale_differentials(differential_scale);
+                let mut sample_aovs = Aovs::default();
+                let radiance = if aovs.is_empty() {
+                    integrator.radiance(&ray, scene, lights, sampler)
+                } else {
+                    integrator.radiance_with_aovs(&ray, scene, lights, &mut sample_aovs, sampler)
+                };
+
+                // Where the sample lies on the image, in pixels from its top
+                // left corner. Offsets from pixel centres are measured in the
+                // direction of `du` and `dv`, which are in [0, 1), so that a
+                // box filter keeps every sample in its own pixel.
+                let (fx, fy) = (i as f64 + du, y as f64 + 1.0 - dv);
+                let (px0, py0) = (first(fx, x0), first(fy, y0));
+                x_weights.clear();
+                x_weights
+                    .extend((px0..=last(fx, x1)).map(|px| filter.evaluate(fx - px as f64 - 0.5)));
+
+                for py in py0..=last(fy, y1) {
+                    let y_weight = filter.evaluate(py as f64 + 0.5 - fy);
+                    for (px, x_weight) in (px0..).zip(&x_weights) {
+                        let weight = x_weight * y_weight;
+                        if weight == 0.0 {
+                            continue;
+                        }
+
+                        let index = ((py - y0) * (x1 - x0) + (px - x0)) as usize;
+                        splats.weights[index] += weight;
+                        let layers = &mut splats.layers;
+                        layers[0][index] = layers[0][index] + radiance * weight;
+                        for (layer, aov) in layers[1..].iter_mut().zip(aovs) {
+                            layer[index] = layer[index] + sample_aovs.get(*aov) * weight;
+                        }
+                    }
+                }
+
+                if let Some(adaptive) = &settings.adaptive {
+                    statistics.push(radiance.luminance());
+                    if adaptive.converged(&statistics) {
+                        break;
+                    }
+                }
+            }
+
+            splats.samples.push(samples);
+        }
+    }
+
+    splats
+}
+
+// Renders the image with the integrator chosen in the settings. `lights` are
+// the emitters that are sampled directly, and `progress` is called with the
+// number of tiles finished and the total as each tile finishes.
+pub fn render<T: Hittable>(
+    camera: &Camera,
+    scene: &T,
+    lights: &World,
+    settings: &RenderSettings,
+    progress: &(dyn Fn(usize, usize) + Sync),
+) -> RenderOutput {
+    // The denoiser is guided by the albedo and normal AOVs, which are only
+    // kept in the output when they were asked for.
+    let mut aovs = settings.aovs.clone();
+    if settings.denoiser.is_some() {
+        for aov in [Aov::Albedo, Aov::Normal] {
+            if !aovs.contains(&aov) {
+                aovs.push(aov);
+            }
+        }
+    }
+
+    let mut output = match settings.integrator {
+        IntegratorKind::Path => {
+            let integrator = PathTracer::new(settings);
+            render_with(
+                camera,
+                scene,
+                lights,
+                &integrator,
+                settings,
+                &aovs,
+                progress,
+            )
+        }
+        IntegratorKind::AmbientOcclusion { samples, distance } => {
+            let integrator = AmbientOcclusion {
+                samples,
+                distance: distance.unwrap_or(f64::INFINITY),
+            };
+            render_with(
+                camera,
+                scene,
+                lights,
+                &integrator,
+                settings,
+                &aovs,
+                progress,
+            )
+        }
+        IntegratorKind::Direct => {
+            let integrator = DirectLighting {
+                max_depth: settings.max_depth,
+                heuristic: settings.heuristic,
+                background: settings.background,
+            };
+            render_with(
+                camera,
+                scene,
+                lights,
+                &integrator,
+                settings,
+                &aovs,
+                progress,
+            )
+        }
+        IntegratorKind::Whitted => {
+            let integrator = Whitted {
+                max_depth: settings.max_depth,
+                background: settings.background,
+            };
+            render_with(
+                camera,
+                scene,
+                lights,
+                &integrator,
+                settings,
+                &aovs,
+                progress,
+            )
+        }
+        IntegratorKind::Debug { channel, raw } => {
+            let integrator = DebugView {
+                channel,
+                raw,
+                path_tracer: PathTracer::new(settings),
+            };
+            render_with(
+                camera,
+                scene,
+                lights,
+                &integrator,
+                settings,
+                &aovs,
+                progress,
+            )
+        }
+    };
+
+    if let Some(denoiser) = &settings.denoiser {
+        let find = |wanted: Aov| {
+            output
+                .aovs
+                .iter()
+                .find(|(aov, _)| *aov == wanted)
+                .map(|(_, framebuffer)| framebuffer)
+                .unwrap()
+        };
+        output.beauty = denoiser.denoise(&output.beauty, find(Aov::Albedo), find(Aov::Normal));
+        output.aovs.retain(|(aov, _)| settings.aovs.contains(aov));
+    }
+
+    output
+}
+
+// Renders the image and `aovs` in tiles spread over rayon's work-stealing
+// thread pool and returns the filtered linear colour of each pixel.
+pub fn render_with<T: Hittable, I: Integrator>(
+    camera: &Camera,
+    scene: &T,
+    lights: &World,
+    integrator: &I,
+    settings: &RenderSettings,
+    aovs: &[Aov],
+    progress: &(dyn Fn(usize, usize) + Sync),
+) -> RenderOutput {
+    let (width, height) = (camera.image_width, camera.image_height);
+    let tiles = tiles(width, height, settings.tile_size);
+    let finished = AtomicUsize::new(0);
+
+    let rendered: Vec<(Tile, Splats)> = tiles
+        .par_iter()
+        .map(|&tile| {
+            let splats = render_tile(camera, scene, lights, integrator, settings, aovs, tile);
+
+            progress(finished.fetch_add(1, Ordering::Relaxed) + 1, tiles.len());
+
+            (tile, splats)
+        })
+        .collect();
+
+    // Splats are added up in tile order, so that the image does not depend on
+    // how the tiles were scheduled.
+    let mut framebuffers: Vec<Framebuffer> = (0..1 + aovs.len())
+        .map(|_| Framebuffer::new(width, height))
+        .collect();
+    let mut weights = vec![0.0; (width * height) as usize];
+    let mut samples = vec![0; (width * height) as usize];
+    for (tile, splats) in rendered {
+        let splat_width = (splats.x1 - splats.x0) as usize;
+        for (row, y) in (splats.y0..splats.y1).enumerate() {
+            let start = (y * width + splats.x0) as usize;
+            let splat_row = row * splat_width..(row + 1) * splat_width;
+            for (weight, splat) in weights[start..]
+                .iter_mut()
+                .zip(&splats.weights[splat_row.clone()])
+            {
+                *weight += splat;
+            }
+            for (framebuffer, layer) in framebuffers.iter_mut().zip(&splats.layers) {
+                for (pixel, splat) in framebuffer.pixels[start..]
+                    .iter_mut()
+                    .zip(&layer[splat_row.clone()])
+                {
+                    *pixel = *pixel + *splat;
+                }
+            }
+        }
+
+        let tile_width = (tile.x1 - tile.x0) as usize;
+        for (row, y) in (tile.y0..tile.y1).enumerate() {
+            let start = (y * width + tile.x0) as usize;
+            samples[start..start + tile_width]
+                .copy_from_slice(&splats.samples[row * tile_width..(row + 1) * tile_width]);
+        }
+    }
+
+    for framebuffer in framebuffers.iter_mut() {
+        for (pixel, &weight) in framebuffer.pixels.iter_mut().zip(&weights) {
+            if weight != 0.0 {
+                *pixel = *pixel * (1.0 / weight);
+            }
+        }
+    }
+    // The sample counts are known only per pixel, so their heatmap is drawn
+    // directly rather than filtered.
+    if let Some(index) = aovs.iter().position(|&aov| aov == Aov::Samples) {
+        let spp = settings.samples_per_pixel as f64;
+        framebuffers[1 + index].pixels = samples
+            .iter()
+            .map(|&count| Colour::heatmap(count as f64 / spp))
+            .collect();
+    }
+
+    let mut framebuffers = framebuffers.into_iter();
+    RenderOutput {
+        beauty: framebuffers.next().unwrap(),
+        aovs: aovs.iter().copied().zip(framebuffers).collect(),
+    }
+}
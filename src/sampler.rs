@@ -0,0 +1,479 @@
+use rand::Rng;
+use rand_pcg::Pcg32;
+use serde::{Deserialize, Serialize};
+
+use crate::render::{sample_rng, splitmix64};
+
+// Supplies the random numbers of one sample of a pixel as a sequence of
+// dimensions: the pixel and lens positions first, then a few for every vertex
+// of the path. Low-discrepancy samplers spread each dimension evenly over the
+// samples of a pixel, so that consumers should take two-dimensional decisions,
+// like a direction, from `get_2d` rather than from two calls to `get_1d`.
+pub trait Sampler {
+    // Moves on to sample `index` of `pixel`, starting from the first dimension.
+    fn start_pixel_sample(&mut self, pixel: u64, index: u32);
+
+    // A value in [0, 1).
+    fn get_1d(&mut self) -> f64;
+
+    fn get_2d(&mut self) -> (f64, f64);
+}
+
+impl<S: Sampler + ?Sized> Sampler for &mut S {
+    fn start_pixel_sample(&mut self, pixel: u64, index: u32) {
+        (**self).start_pixel_sample(pixel, index)
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        (**self).get_1d()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (**self).get_2d()
+    }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum SamplerKind {
+    Independent,
+    Stratified,
+    Halton,
+    Sobol,
+}
+
+impl SamplerKind {
+    pub fn build(self, samples_per_pixel: u32, seed: u64) -> Box<dyn Sampler> {
+        match self {
+            SamplerKind::Independent => Box::new(IndependentSampler::new(seed)),
+            SamplerKind::Stratified => Box::new(StratifiedSampler::new(samples_per_pixel, seed)),
+            SamplerKind::Halton => Box::new(HaltonSampler::new(seed)),
+            SamplerKind::Sobol => Box::new(SobolSampler::new(seed)),
+        }
+    }
+}
+
+// Where the low-discrepancy samplers are in the sequence of a pixel. Each
+// dimension is randomised with its own hash of the pixel, so that neighbouring
+// pixels and dimensions do not correlate.
+#[derive(Debug, Clone, Copy)]
+struct Position {
+    seed: u64,
+    pixel_seed: u64,
+    index: u32,
+    dimension: u32,
+}
+
+impl Position {
+    fn new(seed: u64) -> Self {
+        Position {
+            seed,
+            pixel_seed: splitmix64(seed),
+            index: 0,
+            dimension: 0,
+        }
+    }
+
+    fn start(&mut self, pixel: u64, index: u32) {
+        self.pixel_seed = splitmix64(self.seed ^ splitmix64(pixel));
+        self.index = index;
+        self.dimension = 0;
+    }
+
+    // The dimension to use next and a hash to randomise it with.
+    fn next(&mut self, count: u32) -> (u32, u64) {
+        let dimension = self.dimension;
+        self.dimension += count;
+        (
+            dimension,
+            splitmix64(self.pixel_seed ^ splitmix64(dimension as u64)),
+        )
+    }
+}
+
+// 53 bits of `hash` as a value in [0, 1).
+fn uniform(hash: u64) -> f64 {
+    (hash >> 11) as f64 * (1.0 / (1u64 << 53) as f64)
+}
+
+// Element `i` of a random permutation of 0..`l` picked by `p`, computed without
+// storing the permutation (Kensler, "Correlated Multi-Jittered Sampling").
+fn permutation_element(mut i: u32, l: u32, p: u32) -> u32 {
+    let mut w = l - 1;
+    w |= w >> 1;
+    w |= w >> 2;
+    w |= w >> 4;
+    w |= w >> 8;
+    w |= w >> 16;
+
+    loop {
+        i ^= p;
+        i = i.wrapping_mul(0xe170893d);
+        i ^= p >> 16;
+        i ^= (i & w) >> 4;
+        i ^= p >> 8;
+        i = i.wrapping_mul(0x0929eb3f);
+        i ^= p >> 23;
+        i ^= (i & w) >> 1;
+        i = i.wrapping_mul(1 | p >> 27);
+        i = i.wrapping_mul(0x6935fa69);
+        i ^= (i & w) >> 11;
+        i = i.wrapping_mul(0x74dcb303);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0x9e501cc3);
+        i ^= (i & w) >> 2;
+        i = i.wrapping_mul(0xc860a3df);
+        i &= w;
+        i ^= i >> 5;
+        if i < l {
+            return (i.wrapping_add(p)) % l;
+        }
+    }
+}
+
+// Every dimension drawn independently, as from an ordinary random number
+// generator.
+pub struct IndependentSampler {
+    seed: u64,
+    rng: Pcg32,
+}
+
+impl IndependentSampler {
+    pub fn new(seed: u64) -> Self {
+        IndependentSampler {
+            seed,
+            rng: sample_rng(seed, 0, 0),
+        }
+    }
+}
+
+impl Sampler for IndependentSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, index: u32) {
+        self.rng = sample_rng(self.seed, pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        self.rng.gen()
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.rng.gen(), self.rng.gen())
+    }
+}
+
+// Jittered sampling: every dimension is split into as many strata as there are
+// samples per pixel (a grid of them in two dimensions), and each sample takes a
+// random point in a different stratum, shuffled independently per dimension.
+pub struct StratifiedSampler {
+    samples_per_pixel: u32,
+    position: Position,
+}
+
+impl StratifiedSampler {
+    pub fn new(samples_per_pixel: u32, seed: u64) -> Self {
+        StratifiedSampler {
+            samples_per_pixel: samples_per_pixel.max(1),
+            position: Position::new(seed),
+        }
+    }
+}
+
+impl Sampler for StratifiedSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, index: u32) {
+        self.position.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (_, hash) = self.position.next(1);
+        let count = self.samples_per_pixel;
+        let stratum = permutation_element(self.position.index % count, count, hash as u32);
+        let jitter = uniform(splitmix64(hash ^ self.position.index as u64));
+        (stratum as f64 + jitter) / count as f64
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (_, hash) = self.position.next(2);
+        let x_count = (self.samples_per_pixel as f64).sqrt() as u32;
+        let y_count = self.samples_per_pixel.div_ceil(x_count);
+        let count = x_count * y_count;
+
+        let stratum = permutation_element(self.position.index % count, count, hash as u32);
+        let jitter = splitmix64(hash ^ self.position.index as u64);
+        (
+            ((stratum % x_count) as f64 + uniform(jitter)) / x_count as f64,
+            ((stratum / x_count) as f64 + uniform(splitmix64(jitter))) / y_count as f64,
+        )
+    }
+}
+
+const fn primes<const N: usize>() -> [u64; N] {
+    let mut primes = [0; N];
+    let mut count = 0;
+    let mut candidate = 2;
+    while count < N {
+        let mut i = 0;
+        while i < count && candidate % primes[i] != 0 {
+            i += 1;
+        }
+        if i == count {
+            primes[count] = candidate;
+            count += 1;
+        }
+        candidate += 1;
+    }
+    primes
+}
+
+const PRIMES: [u64; 256] = primes();
+
+// The Halton sequence, with dimension `d` the radical inverse of the sample
+// index in the `d`th prime base. Its digits are Owen scrambled per pixel, which
+// keeps the sequence stratified while making every sample uniformly random.
+// Dimensions beyond the table of primes are drawn independently.
+pub struct HaltonSampler {
+    position: Position,
+}
+
+impl HaltonSampler {
+    pub fn new(seed: u64) -> Self {
+        HaltonSampler {
+            position: Position::new(seed),
+        }
+    }
+}
+
+fn owen_scrambled_radical_inverse(base: u64, mut index: u64, hash: u64) -> f64 {
+    let inverse_base = 1.0 / base as f64;
+    let mut reversed_digits = 0u64;
+    let mut inverse_base_m = 1.0;
+
+    // All digits down to the precision of an f64 are scrambled, including the
+    // leading zeros of the index. Each digit is permuted by a hash of its
+    // position and the digits before it, since prefixes such as 0 and 00
+    // would otherwise share a permutation. With the bases in `PRIMES` the
+    // reversed digits stay below 2^64.
+    let mut position = 0;
+    while inverse_base_m > f64::EPSILON / 2.0 {
+        let next = index / base;
+        let digit = (index - next * base) as u32;
+        let digit_hash = splitmix64(splitmix64(hash ^ position) ^ reversed_digits) as u32;
+        let digit = permutation_element(digit, base as u32, digit_hash) as u64;
+        reversed_digits = reversed_digits * base + digit;
+        inverse_base_m *= inverse_base;
+        index = next;
+        position += 1;
+    }
+    (reversed_digits as f64 * inverse_base_m).min(1.0 - f64::EPSILON / 2.0)
+}
+
+impl Sampler for HaltonSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, index: u32) {
+        self.position.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (dimension, hash) = self.position.next(1);
+        let index = self.position.index as u64;
+        match PRIMES.get(dimension as usize) {
+            Some(&base) => owen_scrambled_radical_inverse(base, index, hash),
+            None => uniform(splitmix64(hash ^ index)),
+        }
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        (self.get_1d(), self.get_1d())
+    }
+}
+
+// The first two dimensions of the Sobol sequence, with the index bits applied
+// to the generator matrices in reverse.
+fn sobol(index: u32, dimension: u32) -> u32 {
+    let mut direction = 1u32 << 31;
+    let mut x = 0;
+    for bit in 0..32 {
+        if index >> bit & 1 != 0 {
+            x ^= direction;
+        }
+        direction = match dimension {
+            0 => direction >> 1,
+            _ => direction ^ direction >> 1,
+        };
+    }
+    x
+}
+
+// Hash-based nested uniform scrambling (Burley, "Practical Hash-based Owen
+// Scrambling").
+fn nested_uniform_scramble(x: u32, seed: u32) -> u32 {
+    let mut x = x.reverse_bits().wrapping_add(seed);
+    x ^= x.wrapping_mul(0x6c50b47c);
+    x ^= x.wrapping_mul(0xb82f1e52);
+    x ^= x.wrapping_mul(0xc7afe638);
+    x ^= x.wrapping_mul(0x8d22f6e6);
+    x.reverse_bits()
+}
+
+fn unit(x: u32) -> f64 {
+    x as f64 * (1.0 / (1u64 << 32) as f64)
+}
+
+// Owen-scrambled Sobol points padded across dimensions: every one or two
+// dimensions take the first one or two Sobol dimensions, scrambled and with
+// the sample order shuffled by their own hash. Prefixes of a power of two
+// samples stay stratified in every pair of dimensions.
+pub struct SobolSampler {
+    position: Position,
+}
+
+impl SobolSampler {
+    pub fn new(seed: u64) -> Self {
+        SobolSampler {
+            position: Position::new(seed),
+        }
+    }
+
+    fn shuffled_index(&self, hash: u64) -> u32 {
+        nested_uniform_scramble(self.position.index, hash as u32)
+    }
+}
+
+impl Sampler for SobolSampler {
+    fn start_pixel_sample(&mut self, pixel: u64, index: u32) {
+        self.position.start(pixel, index);
+    }
+
+    fn get_1d(&mut self) -> f64 {
+        let (_, hash) = self.position.next(1);
+        let index = self.shuffled_index(hash);
+        unit(nested_uniform_scramble(
+            sobol(index, 0),
+            (hash >> 32) as u32,
+        ))
+    }
+
+    fn get_2d(&mut self) -> (f64, f64) {
+        let (_, hash) = self.position.next(2);
+        let index = self.shuffled_index(hash);
+        let scramble = splitmix64(hash);
+        (
+            unit(nested_uniform_scramble(sobol(index, 0), scramble as u32)),
+            unit(nested_uniform_scramble(
+                sobol(index, 1),
+                (scramble >> 32) as u32,
+            )),
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const KINDS: [SamplerKind; 4] = [
+        SamplerKind::Independent,
+        SamplerKind::Stratified,
+        SamplerKind::Halton,
+        SamplerKind::Sobol,
+    ];
+
+    // Dimension `dimension` of the first `count` samples of `pixel`.
+    fn dimension_values(
+        sampler: &mut dyn Sampler,
+        pixel: u64,
+        count: u32,
+        dimension: u32,
+    ) -> Vec<f64> {
+        (0..count)
+            .map(|index| {
+                sampler.start_pixel_sample(pixel, index);
+                for _ in 0..dimension {
+                    sampler.get_1d();
+                }
+                sampler.get_1d()
+            })
+            .collect()
+    }
+
+    #[test]
+    fn first_samples_are_uniform() {
+        let pixels = 10_000;
+        for kind in KINDS {
+            let mut sampler = kind.build(1, 7);
+            let mut sums = [0.0; 8];
+            for pixel in 0..pixels {
+                sampler.start_pixel_sample(pixel, 0);
+                for sum in &mut sums {
+                    let value = sampler.get_1d();
+                    assert!(value > 0.0 && value < 1.0, "{kind:?}: {value}");
+                    *sum += value;
+                }
+            }
+
+            for (dimension, sum) in sums.iter().enumerate() {
+                let mean = sum / pixels as f64;
+                assert!(
+                    (mean - 0.5).abs() < 0.02,
+                    "{kind:?} dimension {dimension}: mean {mean}"
+                );
+            }
+        }
+    }
+
+    #[test]
+    fn few_samples_are_uniform() {
+        let pixels = 2_000;
+        for kind in KINDS {
+            for count in [2, 4, 16, 64] {
+                let mut sampler = kind.build(count, 3);
+                for dimension in 0..4 {
+                    let sum: f64 = (0..pixels)
+                        .flat_map(|pixel| {
+                            dimension_values(sampler.as_mut(), pixel, count, dimension)
+                        })
+                        .sum();
+                    let mean = sum / (pixels * count as u64) as f64;
+                    assert!(
+                        (mean - 0.5).abs() < 0.02,
+                        "{kind:?} at {count} spp, dimension {dimension}: mean {mean}"
+                    );
+                }
+            }
+        }
+    }
+
+    // Each of the first `strata` samples of a pixel falls in its own interval
+    // of width 1 / `strata`.
+    fn assert_stratified(kind: SamplerKind, samples_per_pixel: u32, dimension: u32, strata: u32) {
+        let mut sampler = kind.build(samples_per_pixel, 11);
+        for pixel in 0..100 {
+            let mut hits = vec![false; strata as usize];
+            for value in dimension_values(sampler.as_mut(), pixel, strata, dimension) {
+                let stratum = (value * strata as f64) as usize;
+                assert!(
+                    !hits[stratum],
+                    "{kind:?} dimension {dimension}: two of {strata} samples in stratum {stratum}"
+                );
+                hits[stratum] = true;
+            }
+        }
+    }
+
+    #[test]
+    fn samples_are_stratified() {
+        for count in [1, 2, 4, 16, 64] {
+            for dimension in 0..4 {
+                assert_stratified(SamplerKind::Stratified, count, dimension, count);
+                assert_stratified(SamplerKind::Sobol, count, dimension, count);
+            }
+        }
+        for strata in [1, 2, 4, 8, 16, 64] {
+            assert_stratified(SamplerKind::Halton, strata, 0, strata);
+        }
+        for strata in [1, 3, 9, 27] {
+            assert_stratified(SamplerKind::Halton, strata, 1, strata);
+        }
+        for strata in [1, 5, 25] {
+            assert_stratified(SamplerKind::Halton, strata, 2, strata);
+        }
+    }
+}
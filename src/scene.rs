@@ -0,0 +1,1247 @@
+use std::{
+    cell::Cell,
+    collections::BTreeMap,
+    fmt, fs,
+    marker::PhantomData,
+    ops::Range,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use cgmath::{Deg, InnerSpace, Matrix4, Vector3};
+use serde::{de, Deserialize, Deserializer, Serialize};
+use toml::Spanned;
+
+use crate::{
+    adaptive::AdaptiveSampling,
+    aov::Aov,
+    camera::Camera,
+    colour::Colour,
+    denoise::Denoiser,
+    filter::Filter,
+    instance::Instance,
+    integrator::IntegratorKind,
+    obj::load_obj,
+    render::{Background, Heuristic, RenderSettings},
+    sampler::SamplerKind,
+    shapes::{Cuboid, Hittable, Indexed, Material, MaterialIndexed, Plane, Quad, Sphere, World},
+    texture::{Bump, ImageCache, ImageFilter, Texture, WrapMode},
+    tonemap::DisplayTransform,
+    volume::ConstantMedium,
+};
+
+pub type Vec3 = [f64; 3];
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct CameraDescription {
+    pub look_from: Vec3,
+    pub look_at: Vec3,
+    #[serde(default = "default_vup")]
+    pub vup: Vec3,
+    pub vertical_fov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    pub focus_dist: Option<f64>,
+}
+
+fn default_vup() -> Vec3 {
+    [0.0, 1.0, 0.0]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct RenderDescription {
+    pub width: u32,
+    pub height: u32,
+    pub samples_per_pixel: u32,
+    pub min_depth: u32,
+    pub max_depth: u32,
+    pub seed: u64,
+    pub background: Option<Vec3>,
+    pub heuristic: Heuristic,
+    pub integrator: IntegratorKind,
+    pub aovs: Vec<Aov>,
+    pub denoise: Option<Denoiser>,
+    pub adaptive: Option<AdaptiveSampling>,
+    pub sampler: SamplerKind,
+    pub filter: Filter,
+    pub display: DisplayTransform,
+}
+
+impl Default for RenderDescription {
+    fn default() -> Self {
+        let settings = RenderSettings::default();
+        RenderDescription {
+            width: 1200,
+            height: 800,
+            samples_per_pixel: settings.samples_per_pixel,
+            min_depth: settings.min_depth,
+            max_depth: settings.max_depth,
+            seed: settings.seed,
+            background: None,
+            heuristic: settings.heuristic,
+            integrator: settings.integrator,
+            aovs: settings.aovs,
+            denoise: settings.denoiser,
+            adaptive: settings.adaptive,
+            sampler: settings.sampler,
+            filter: settings.filter,
+            display: DisplayTransform::default(),
+        }
+    }
+}
+
+impl RenderDescription {
+    // Catches settings that would fail or render nothing, before any of the
+    // scene is built.
+    fn validate(&self) -> Result<(), SceneError> {
+        let invalid = |message: String| Err(SceneError::Settings(message));
+        for (name, value) in [
+            ("width", self.width),
+            ("height", self.height),
+            ("samples_per_pixel", self.samples_per_pixel),
+        ] {
+            if value == 0 {
+                return invalid(format!("`{name}` must be at least 1"));
+            }
+        }
+        if let Some(adaptive) = &self.adaptive {
+            if adaptive.min_samples > self.samples_per_pixel {
+                return invalid(format!(
+                    "adaptive `min_samples` ({}) is more than `samples_per_pixel` ({})",
+                    adaptive.min_samples, self.samples_per_pixel
+                ));
+            }
+        }
+        Ok(())
+    }
+}
+
+// Serde buffers internally tagged enums until it has found the tag, which
+// loses where in the file their fields were. Materials and textures are read
+// through this instead: `type` picks the variant of an externally tagged
+// version of the enum, which then reads the rest of the table straight from
+// the file, so that errors point at the offending key. Keys before `type`
+// are buffered.
+trait Tagged: Sized {
+    fn deserialize_fields<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error>;
+}
+
+fn deserialize_tagged<'de, D: Deserializer<'de>, T: Tagged>(
+    deserializer: D,
+) -> Result<T, D::Error> {
+    struct Visitor<T>(PhantomData<T>);
+
+    impl<'de, T: Tagged> de::Visitor<'de> for Visitor<T> {
+        type Value = T;
+
+        fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+            f.write_str("a table with a `type`")
+        }
+
+        fn visit_map<A: de::MapAccess<'de>>(self, mut map: A) -> Result<T, A::Error> {
+            let mut buffered = Vec::new();
+            while let Some(key) = map.next_key::<String>()? {
+                if key == "type" {
+                    return T::deserialize_fields(TaggedTable {
+                        variant: map.next_value()?,
+                        buffered: buffered.into_iter(),
+                        value: None,
+                        map,
+                    });
+                }
+                buffered.push((key, map.next_value::<toml::Value>()?));
+            }
+            Err(de::Error::missing_field("type"))
+        }
+    }
+
+    deserializer.deserialize_map(Visitor(PhantomData))
+}
+
+// A table whose `type` has been read, presented as the variant of an enum
+// with the remaining keys as its fields.
+struct TaggedTable<A> {
+    variant: String,
+    buffered: std::vec::IntoIter<(String, toml::Value)>,
+    // The value of the buffered key last returned.
+    value: Option<toml::Value>,
+    map: A,
+}
+
+impl<'de, A: de::MapAccess<'de>> Deserializer<'de> for TaggedTable<A> {
+    type Error = A::Error;
+
+    fn deserialize_enum<V: de::Visitor<'de>>(
+        self,
+        _name: &'static str,
+        _variants: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        visitor.visit_enum(self)
+    }
+
+    fn deserialize_any<V: de::Visitor<'de>>(self, visitor: V) -> Result<V::Value, A::Error> {
+        Err(de::Error::invalid_type(de::Unexpected::Map, &visitor))
+    }
+
+    serde::forward_to_deserialize_any! {
+        bool i8 i16 i32 i64 i128 u8 u16 u32 u64 u128 f32 f64 char str string bytes byte_buf
+        option unit unit_struct newtype_struct seq tuple tuple_struct map struct identifier
+        ignored_any
+    }
+}
+
+impl<'de, A: de::MapAccess<'de>> de::EnumAccess<'de> for TaggedTable<A> {
+    type Error = A::Error;
+    type Variant = Self;
+
+    fn variant_seed<V: de::DeserializeSeed<'de>>(
+        mut self,
+        seed: V,
+    ) -> Result<(V::Value, Self), A::Error> {
+        let variant = std::mem::take(&mut self.variant);
+        let variant = seed.deserialize(de::IntoDeserializer::into_deserializer(variant))?;
+        Ok((variant, self))
+    }
+}
+
+impl<'de, A: de::MapAccess<'de>> de::VariantAccess<'de> for TaggedTable<A> {
+    type Error = A::Error;
+
+    fn struct_variant<V: de::Visitor<'de>>(
+        self,
+        _fields: &'static [&'static str],
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        visitor.visit_map(self)
+    }
+
+    fn unit_variant(self) -> Result<(), A::Error> {
+        Err(de::Error::invalid_type(
+            de::Unexpected::Map,
+            &"a unit variant",
+        ))
+    }
+
+    fn newtype_variant_seed<T: de::DeserializeSeed<'de>>(
+        self,
+        _seed: T,
+    ) -> Result<T::Value, A::Error> {
+        Err(de::Error::invalid_type(
+            de::Unexpected::Map,
+            &"a newtype variant",
+        ))
+    }
+
+    fn tuple_variant<V: de::Visitor<'de>>(
+        self,
+        _len: usize,
+        visitor: V,
+    ) -> Result<V::Value, A::Error> {
+        Err(de::Error::invalid_type(de::Unexpected::Map, &visitor))
+    }
+}
+
+impl<'de, A: de::MapAccess<'de>> de::MapAccess<'de> for TaggedTable<A> {
+    type Error = A::Error;
+
+    fn next_key_seed<K: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: K,
+    ) -> Result<Option<K::Value>, A::Error> {
+        match self.buffered.next() {
+            Some((key, value)) => {
+                self.value = Some(value);
+                seed.deserialize(de::IntoDeserializer::into_deserializer(key))
+                    .map(Some)
+            }
+            None => self.map.next_key_seed(seed),
+        }
+    }
+
+    fn next_value_seed<V: de::DeserializeSeed<'de>>(
+        &mut self,
+        seed: V,
+    ) -> Result<V::Value, A::Error> {
+        match self.value.take() {
+            Some(value) => seed.deserialize(value).map_err(de::Error::custom),
+            None => self.map.next_value_seed(seed),
+        }
+    }
+}
+
+// A material parameter, written as a number, a colour or a texture table.
+#[derive(Debug, Clone, Serialize)]
+#[serde(untagged)]
+pub enum TextureDescription {
+    Value(f64),
+    Colour(Vec3),
+    Texture(TextureKind),
+}
+
+// Picks the form by the kind of value rather than trying each in turn as an
+// untagged enum would, so that mistakes inside a texture table are reported
+// by `TextureKind` itself, where they are.
+impl<'de> Deserialize<'de> for TextureDescription {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        struct Visitor;
+
+        impl<'de> de::Visitor<'de> for Visitor {
+            type Value = TextureDescription;
+
+            fn expecting(&self, f: &mut fmt::Formatter) -> fmt::Result {
+                f.write_str("a number, an [r, g, b] colour or a texture table")
+            }
+
+            fn visit_f64<E: de::Error>(self, value: f64) -> Result<Self::Value, E> {
+                Ok(TextureDescription::Value(value))
+            }
+
+            fn visit_i64<E: de::Error>(self, value: i64) -> Result<Self::Value, E> {
+                Ok(TextureDescription::Value(value as f64))
+            }
+
+            fn visit_u64<E: de::Error>(self, value: u64) -> Result<Self::Value, E> {
+                Ok(TextureDescription::Value(value as f64))
+            }
+
+            fn visit_seq<A: de::SeqAccess<'de>>(self, seq: A) -> Result<Self::Value, A::Error> {
+                Vec3::deserialize(de::value::SeqAccessDeserializer::new(seq))
+                    .map(TextureDescription::Colour)
+            }
+
+            fn visit_map<A: de::MapAccess<'de>>(self, map: A) -> Result<Self::Value, A::Error> {
+                TextureKind::deserialize(de::value::MapAccessDeserializer::new(map))
+                    .map(TextureDescription::Texture)
+            }
+        }
+
+        deserializer.deserialize_any(Visitor)
+    }
+}
+
+impl From<f64> for TextureDescription {
+    fn from(value: f64) -> Self {
+        TextureDescription::Value(value)
+    }
+}
+
+impl From<Vec3> for TextureDescription {
+    fn from(colour: Vec3) -> Self {
+        TextureDescription::Colour(colour)
+    }
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum TextureKind {
+    Checker {
+        scale: f64,
+        uv: bool,
+        even: Box<TextureDescription>,
+        odd: Box<TextureDescription>,
+    },
+    Gradient {
+        from: Vec3,
+        to: Vec3,
+    },
+    Image {
+        path: PathBuf,
+        filter: ImageFilter,
+        wrap: WrapMode,
+        // Whether 8 and 16-bit images hold data, such as fuzz, rather than
+        // sRGB encoded colour.
+        linear: bool,
+    },
+    Noise {
+        scale: f64,
+    },
+    Marble {
+        scale: f64,
+        turbulence: f64,
+        from: Box<TextureDescription>,
+        to: Box<TextureDescription>,
+    },
+    Wood {
+        scale: f64,
+        turbulence: f64,
+        from: Box<TextureDescription>,
+        to: Box<TextureDescription>,
+    },
+    Granite {
+        scale: f64,
+        from: Box<TextureDescription>,
+        to: Box<TextureDescription>,
+    },
+}
+
+impl<'de> Deserialize<'de> for TextureKind {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_tagged(deserializer)
+    }
+}
+
+impl Tagged for TextureKind {
+    fn deserialize_fields<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        TextureKindFields::deserialize(deserializer)
+    }
+}
+
+// The fields of each kind of `TextureKind`, read by `deserialize_tagged`.
+#[derive(Deserialize)]
+#[serde(remote = "TextureKind", rename_all = "snake_case", deny_unknown_fields)]
+enum TextureKindFields {
+    Checker {
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        #[serde(default)]
+        uv: bool,
+        even: Box<TextureDescription>,
+        odd: Box<TextureDescription>,
+    },
+    Gradient {
+        from: Vec3,
+        to: Vec3,
+    },
+    Image {
+        path: PathBuf,
+        #[serde(default)]
+        filter: ImageFilter,
+        #[serde(default)]
+        wrap: WrapMode,
+        #[serde(default)]
+        linear: bool,
+    },
+    Noise {
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+    },
+    Marble {
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        #[serde(default = "default_turbulence")]
+        turbulence: f64,
+        #[serde(default = "default_from")]
+        from: Box<TextureDescription>,
+        #[serde(default = "default_to")]
+        to: Box<TextureDescription>,
+    },
+    Wood {
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        #[serde(default = "default_turbulence")]
+        turbulence: f64,
+        #[serde(default = "default_from")]
+        from: Box<TextureDescription>,
+        #[serde(default = "default_to")]
+        to: Box<TextureDescription>,
+    },
+    Granite {
+        #[serde(default = "default_texture_scale")]
+        scale: f64,
+        #[serde(default = "default_from")]
+        from: Box<TextureDescription>,
+        #[serde(default = "default_to")]
+        to: Box<TextureDescription>,
+    },
+}
+
+fn default_texture_scale() -> f64 {
+    1.0
+}
+
+fn default_turbulence() -> f64 {
+    0.5
+}
+
+// Without colours, the solid textures give their pattern in [0, 1], ready to
+// drive fuzz or a bump.
+fn default_from() -> Box<TextureDescription> {
+    Box::new(0.0.into())
+}
+
+fn default_to() -> Box<TextureDescription> {
+    Box::new(1.0.into())
+}
+
+// A height texture for `Bump`, scaled into scene units by `scale`.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct BumpDescription {
+    pub height: TextureDescription,
+    #[serde(default = "default_bump_scale")]
+    pub scale: f64,
+}
+
+fn default_bump_scale() -> f64 {
+    1.0
+}
+
+#[derive(Debug, Clone, Serialize)]
+#[serde(tag = "type", rename_all = "snake_case")]
+pub enum MaterialDescription {
+    Lambertian {
+        albedo: TextureDescription,
+        bump: Option<BumpDescription>,
+    },
+    Metal {
+        albedo: TextureDescription,
+        fuzz: TextureDescription,
+        bump: Option<BumpDescription>,
+    },
+    Dielectric {
+        index_of_refraction: TextureDescription,
+        bump: Option<BumpDescription>,
+    },
+    Isotropic {
+        albedo: TextureDescription,
+    },
+    DiffuseLight {
+        emit: TextureDescription,
+    },
+}
+
+impl<'de> Deserialize<'de> for MaterialDescription {
+    fn deserialize<D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        deserialize_tagged(deserializer)
+    }
+}
+
+impl Tagged for MaterialDescription {
+    fn deserialize_fields<'de, D: Deserializer<'de>>(deserializer: D) -> Result<Self, D::Error> {
+        MaterialDescriptionFields::deserialize(deserializer)
+    }
+}
+
+// The fields of each kind of `MaterialDescription`, read by `deserialize_tagged`.
+#[derive(Deserialize)]
+#[serde(
+    remote = "MaterialDescription",
+    rename_all = "snake_case",
+    deny_unknown_fields
+)]
+enum MaterialDescriptionFields {
+    Lambertian {
+        albedo: TextureDescription,
+        bump: Option<BumpDescription>,
+    },
+    Metal {
+        albedo: TextureDescription,
+        fuzz: TextureDescription,
+        bump: Option<BumpDescription>,
+    },
+    Dielectric {
+        index_of_refraction: TextureDescription,
+        bump: Option<BumpDescription>,
+    },
+    Isotropic {
+        albedo: TextureDescription,
+    },
+    DiffuseLight {
+        emit: TextureDescription,
+    },
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(tag = "type", rename_all = "snake_case", deny_unknown_fields)]
+pub enum ShapeDescription {
+    Sphere {
+        center: Vec3,
+        radius: f64,
+        material: String,
+    },
+    Plane {
+        point: Vec3,
+        normal: Vec3,
+        material: String,
+    },
+    Quad {
+        corner: Vec3,
+        u: Vec3,
+        v: Vec3,
+        material: String,
+    },
+    Box {
+        min: Vec3,
+        max: Vec3,
+        material: String,
+    },
+    Mesh {
+        path: PathBuf,
+        material: Option<String>,
+    },
+    Volume {
+        boundary: Box<ShapeDescription>,
+        density: f64,
+        albedo: Vec3,
+    },
+    Instance {
+        shape: Box<ShapeDescription>,
+        #[serde(default)]
+        translate: Vec3,
+        #[serde(default)]
+        rotate: Vec3,
+        #[serde(default = "default_scale")]
+        scale: Vec3,
+    },
+}
+
+fn default_scale() -> Vec3 {
+    [1.0, 1.0, 1.0]
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SceneFile {
+    pub camera: CameraDescription,
+    #[serde(default)]
+    pub render: RenderDescription,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    #[serde(default)]
+    pub shapes: Vec<Spanned<ShapeDescription>>,
+    #[serde(skip)]
+    pub(crate) origin: Option<Origin>,
+}
+
+// Where a scene file was read from, used to resolve relative mesh paths and
+// to turn byte spans into line and column numbers.
+#[derive(Debug, Clone)]
+pub(crate) struct Origin {
+    path: PathBuf,
+    source: String,
+}
+
+pub struct Scene {
+    pub camera: Camera,
+    pub world: World,
+    pub lights: World,
+    pub settings: RenderSettings,
+    pub display: DisplayTransform,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io {
+        path: PathBuf,
+        source: std::io::Error,
+    },
+    Parse {
+        path: PathBuf,
+        source: toml::de::Error,
+    },
+    Serialize(toml::ser::Error),
+    Settings(String),
+    Invalid {
+        span: Range<usize>,
+        location: Option<(PathBuf, usize, usize)>,
+        message: String,
+    },
+}
+
+impl fmt::Display for SceneError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io { path, source } => write!(f, "{}: {source}", path.display()),
+            SceneError::Parse { path, source } => write!(f, "{}: {source}", path.display()),
+            SceneError::Serialize(source) => write!(f, "could not serialize scene: {source}"),
+            SceneError::Settings(message) => write!(f, "invalid render settings: {message}"),
+            SceneError::Invalid {
+                location: Some((path, line, column)),
+                message,
+                ..
+            } => write!(f, "{}:{line}:{column}: {message}", path.display()),
+            SceneError::Invalid { message, .. } => write!(f, "{message}"),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+fn line_and_column(source: &str, offset: usize) -> (usize, usize) {
+    let before = &source[..offset.min(source.len())];
+    let line = before.matches('\n').count() + 1;
+    let column = before.chars().rev().take_while(|&c| c != '\n').count() + 1;
+    (line, column)
+}
+
+fn vector(v: Vec3) -> Vector3<f64> {
+    Vector3::new(v[0], v[1], v[2])
+}
+
+fn colour(c: Vec3) -> Colour<f64> {
+    Colour::new(c[0], c[1], c[2])
+}
+
+struct Textures<'a> {
+    base_dir: &'a Path,
+    images: &'a ImageCache,
+}
+
+impl Textures<'_> {
+    fn texture(&self, description: &TextureDescription) -> Result<Texture, SceneError> {
+        let kind = match description {
+            TextureDescription::Value(value) => return Ok(Texture::from(*value)),
+            TextureDescription::Colour(c) => return Ok(Texture::Solid(colour(*c))),
+            TextureDescription::Texture(kind) => kind,
+        };
+
+        Ok(match kind {
+            TextureKind::Checker {
+                scale,
+                uv,
+                even,
+                odd,
+            } => Texture::Checker {
+                scale: *scale,
+                uv: *uv,
+                even: Box::new(self.texture(even)?),
+                odd: Box::new(self.texture(odd)?),
+            },
+            TextureKind::Gradient { from, to } => Texture::Gradient {
+                from: colour(*from),
+                to: colour(*to),
+            },
+            TextureKind::Image {
+                path,
+                filter,
+                wrap,
+                linear,
+            } => {
+                let path = self.base_dir.join(path);
+                let image = self
+                    .images
+                    .open(&path, !linear)
+                    .map_err(|source| SceneError::Io { path, source })?;
+                Texture::Image {
+                    image,
+                    filter: *filter,
+                    wrap: *wrap,
+                }
+            }
+            TextureKind::Noise { scale } => Texture::Noise { scale: *scale },
+            TextureKind::Marble {
+                scale,
+                turbulence,
+                from,
+                to,
+            } => Texture::Marble {
+                scale: *scale,
+                turbulence: *turbulence,
+                from: Box::new(self.texture(from)?),
+                to: Box::new(self.texture(to)?),
+            },
+            TextureKind::Wood {
+                scale,
+                turbulence,
+                from,
+                to,
+            } => Texture::Wood {
+                scale: *scale,
+                turbulence: *turbulence,
+                from: Box::new(self.texture(from)?),
+                to: Box::new(self.texture(to)?),
+            },
+            TextureKind::Granite { scale, from, to } => Texture::Granite {
+                scale: *scale,
+                from: Box::new(self.texture(from)?),
+                to: Box::new(self.texture(to)?),
+            },
+        })
+    }
+
+    fn bump(&self, description: Option<&BumpDescription>) -> Result<Option<Bump>, SceneError> {
+        description
+            .map(|bump| {
+                Ok(Bump {
+                    height: self.texture(&bump.height)?,
+                    scale: bump.scale,
+                })
+            })
+            .transpose()
+    }
+
+    fn material(&self, description: &MaterialDescription) -> Result<Material, SceneError> {
+        Ok(match description {
+            MaterialDescription::Lambertian { albedo, bump } => Material::Lambetarian {
+                albedo: self.texture(albedo)?,
+                bump: self.bump(bump.as_ref())?,
+            },
+            MaterialDescription::Metal { albedo, fuzz, bump } => Material::Metal {
+                albedo: self.texture(albedo)?,
+                fuzz: self.texture(fuzz)?,
+                bump: self.bump(bump.as_ref())?,
+            },
+            MaterialDescription::Dielectric {
+                index_of_refraction,
+                bump,
+            } => Material::Dielectric {
+                index_of_refraction: self.texture(index_of_refraction)?,
+                bump: self.bump(bump.as_ref())?,
+            },
+            MaterialDescription::Isotropic { albedo } => Material::Isotropic {
+                albedo: self.texture(albedo)?,
+            },
+            MaterialDescription::DiffuseLight { emit } => Material::DiffuseLight {
+                emit: self.texture(emit)?,
+            },
+        })
+    }
+}
+
+struct Builder<'a> {
+    materials: &'a BTreeMap<String, Material>,
+    images: &'a ImageCache,
+    base_dir: &'a Path,
+    seed: u64,
+    span: Range<usize>,
+    // The index for the next material that is not one of the named ones.
+    next_material: &'a Cell<usize>,
+}
+
+impl Builder<'_> {
+    fn invalid(&self, message: String) -> SceneError {
+        SceneError::Invalid {
+            span: self.span.clone(),
+            location: None,
+            message,
+        }
+    }
+
+    fn material(&self, name: &str) -> Result<Material, SceneError> {
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| self.invalid(format!("unknown material `{name}`")))
+    }
+
+    // Named materials are numbered in order, and the ones that meshes and
+    // volumes bring along follow them.
+    fn material_index(&self, name: &str) -> usize {
+        self.materials
+            .keys()
+            .take_while(|key| key.as_str() < name)
+            .count()
+    }
+
+    fn unnamed_material_index(&self) -> usize {
+        let index = self.next_material.get();
+        self.next_material.set(index + 1);
+        index
+    }
+
+    // Builds the shape and adds the parts of it that emit light and can be
+    // sampled to `lights`. Planes are unbounded and volumes do not emit, so
+    // neither is ever sampled.
+    fn shape(
+        &self,
+        description: &ShapeDescription,
+        lights: &mut World,
+    ) -> Result<Arc<dyn Hittable>, SceneError> {
+        let object: Arc<dyn Hittable> = match description {
+            ShapeDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => Arc::new(Sphere {
+                center: vector(*center),
+                radius: *radius,
+                material: self.material(material)?,
+            }),
+            ShapeDescription::Plane {
+                point,
+                normal,
+                material,
+            } => Arc::new(Plane {
+                point: vector(*point),
+                normal: vector(*normal),
+                material: self.material(material)?,
+            }),
+            ShapeDescription::Quad {
+                corner,
+                u,
+                v,
+                material,
+            } => Arc::new(Quad {
+                corner: vector(*corner),
+                u: vector(*u),
+                v: vector(*v),
+                material: self.material(material)?,
+            }),
+            ShapeDescription::Box { min, max, material } => Arc::new(Cuboid::new(
+                vector(*min),
+                vector(*max),
+                self.material(material)?,
+            )),
+            ShapeDescription::Mesh { path, material } => {
+                let default_material = match material {
+                    Some(name) => self.material(name)?,
+                    None => Material::Lambetarian {
+                        albedo: 0.8.into(),
+                        bump: None,
+                    },
+                };
+                let path = self.base_dir.join(path);
+                let meshes = load_obj(&path, default_material, self.images)
+                    .map_err(|e| self.invalid(format!("{}: {e}", path.display())))?;
+
+                let mut world = World::new();
+                for mesh in meshes {
+                    let emitter = matches!(mesh.material(), Material::DiffuseLight { .. });
+                    let mesh: Arc<dyn Hittable> = Arc::new(MaterialIndexed {
+                        object: Arc::new(mesh),
+                        index: self.unnamed_material_index(),
+                    });
+                    if emitter {
+                        lights.objects.push(mesh.clone());
+                    }
+                    world.objects.push(mesh);
+                }
+                Arc::new(world)
+            }
+            ShapeDescription::Volume {
+                boundary,
+                density,
+                albedo,
+            } => Arc::new(MaterialIndexed {
+                object: Arc::new(ConstantMedium::new(
+                    self.shape(boundary, &mut World::new())?,
+                    *density,
+                    colour(*albedo),
+                    self.seed,
+                )),
+                index: self.unnamed_material_index(),
+            }),
+            ShapeDescription::Instance {
+                shape,
+                translate,
+                rotate,
+                scale,
+            } => {
+                let transform = Matrix4::from_translation(vector(*translate))
+                    * Matrix4::from_angle_z(Deg(rotate[2]))
+                    * Matrix4::from_angle_y(Deg(rotate[1]))
+                    * Matrix4::from_angle_x(Deg(rotate[0]))
+                    * Matrix4::from_nonuniform_scale(scale[0], scale[1], scale[2]);
+
+                let mut inner = World::new();
+                let object = self.shape(shape, &mut inner)?;
+                let singular = || self.invalid("instance transform is singular".into());
+                if !inner.objects.is_empty() {
+                    lights.add(Instance::new(Arc::new(inner), transform).ok_or_else(singular)?);
+                }
+                Arc::new(Instance::new(object, transform).ok_or_else(singular)?)
+            }
+        };
+
+        let (ShapeDescription::Sphere { material, .. }
+        | ShapeDescription::Plane { material, .. }
+        | ShapeDescription::Quad { material, .. }
+        | ShapeDescription::Box { material, .. }) = description
+        else {
+            return Ok(object);
+        };
+
+        let object: Arc<dyn Hittable> = Arc::new(MaterialIndexed {
+            object,
+            index: self.material_index(material),
+        });
+        let emitter = matches!(
+            self.materials.get(material),
+            Some(Material::DiffuseLight { .. })
+        );
+        if emitter && !matches!(description, ShapeDescription::Plane { .. }) {
+            lights.objects.push(object.clone());
+        }
+        Ok(object)
+    }
+}
+
+impl SceneFile {
+    pub fn open<P: AsRef<Path>>(path: P) -> Result<SceneFile, SceneError> {
+        let path = path.as_ref();
+        let source = fs::read_to_string(path).map_err(|source| SceneError::Io {
+            path: path.to_path_buf(),
+            source,
+        })?;
+        let mut file: SceneFile = toml::from_str(&source).map_err(|source| SceneError::Parse {
+            path: path.to_path_buf(),
+            source,
+        })?;
+
+        file.origin = Some(Origin {
+            path: path.to_path_buf(),
+            source,
+        });
+        Ok(file)
+    }
+
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> Result<(), SceneError> {
+        let path = path.as_ref();
+        let text = toml::to_string(self).map_err(SceneError::Serialize)?;
+        fs::write(path, text).map_err(|source| SceneError::Io {
+            path: path.to_path_buf(),
+            source,
+        })
+    }
+
+    pub fn build(&self) -> Result<Scene, SceneError> {
+        self.build_world()
+            .map_err(|error| match (error, &self.origin) {
+                (SceneError::Invalid { span, message, .. }, Some(origin)) => {
+                    let (line, column) = line_and_column(&origin.source, span.start);
+                    SceneError::Invalid {
+                        location: Some((origin.path.clone(), line, column)),
+                        span,
+                        message,
+                    }
+                }
+                (error, _) => error,
+            })
+    }
+
+    fn build_world(&self) -> Result<Scene, SceneError> {
+        self.render.validate()?;
+
+        let base_dir = match &self.origin {
+            Some(origin) => origin.path.parent().unwrap_or(Path::new(".")),
+            None => Path::new("."),
+        };
+
+        let look_from = vector(self.camera.look_from);
+        let look_at = vector(self.camera.look_at);
+
+        let camera = Camera::new(
+            look_from,
+            look_at,
+            vector(self.camera.vup),
+            self.camera.vertical_fov,
+            self.camera.aperture,
+            self.camera
+                .focus_dist
+                .unwrap_or_else(|| (look_from - look_at).magnitude()),
+            self.render.width,
+            self.render.height,
+        );
+
+        let images = ImageCache::default();
+        let textures = Textures {
+            base_dir,
+            images: &images,
+        };
+        let materials = self
+            .materials
+            .iter()
+            .map(|(name, description)| Ok((name.clone(), textures.material(description)?)))
+            .collect::<Result<BTreeMap<_, _>, SceneError>>()?;
+
+        let mut world = World::new();
+        let mut lights = World::new();
+        let next_material = Cell::new(materials.len());
+        for (index, shape) in self.shapes.iter().enumerate() {
+            let builder = Builder {
+                materials: &materials,
+                images: &images,
+                base_dir,
+                seed: self.render.seed,
+                span: shape.span(),
+                next_material: &next_material,
+            };
+            world.objects.push(Arc::new(Indexed {
+                object: builder.shape(shape.get_ref(), &mut lights)?,
+                index,
+            }));
+        }
+
+        let settings = RenderSettings {
+            samples_per_pixel: self.render.samples_per_pixel,
+            min_depth: self.render.min_depth,
+            max_depth: self.render.max_depth,
+            seed: self.render.seed,
+            background: match self.render.background {
+                Some(background) => Background::Solid(colour(background)),
+                None => Background::Sky,
+            },
+            heuristic: self.render.heuristic,
+            integrator: self.render.integrator,
+            aovs: self.render.aovs.clone(),
+            denoiser: self.render.denoise,
+            adaptive: self.render.adaptive,
+            sampler: self.render.sampler,
+            filter: self.render.filter,
+            ..RenderSettings::default()
+        };
+
+        Ok(Scene {
+            camera,
+            world,
+            lights,
+            settings,
+            display: self.render.display,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::{ray::Ray, render::render};
+
+    const CAMERA: &str =
+        "[camera]\nlook_from = [0, 0, 3]\nlook_at = [0, 0, 0]\nvertical_fov = 40\n\n";
+
+    // The text of the scene that a parse error points at.
+    fn parse_error(materials: &str) -> (String, String) {
+        let source = format!("{CAMERA}{materials}");
+        let error = toml::from_str::<SceneFile>(&source).unwrap_err();
+        let span = error.span().expect("error without a location");
+        (source[span].to_string(), error.message().to_string())
+    }
+
+    #[test]
+    fn texture_errors_point_at_the_key() {
+        let (at, message) = parse_error(
+            "[materials.m]\ntype = \"lambertian\"\n\
+             albedo = { type = \"checker\", scal = 2, even = 0.1, odd = 0.9 }\n",
+        );
+        assert_eq!(at, "scal");
+        assert!(message.contains("unknown field `scal`"), "{message}");
+
+        let (at, message) = parse_error(
+            "[materials.m]\ntype = \"lambertian\"\n\
+             albedo = { scale = 2, type = \"checker\", even = [1, 2], odd = 0.9 }\n",
+        );
+        assert_eq!(at, "[1, 2]");
+        assert!(message.contains("invalid length 2"), "{message}");
+    }
+
+    #[test]
+    fn material_errors_point_at_the_key() {
+        let (at, message) =
+            parse_error("[materials.m]\nalbedo = 0.5\ntype = \"lambertian\"\nfuz = 0.3\n");
+        assert_eq!(at, "fuz");
+        assert!(message.contains("unknown field `fuz`"), "{message}");
+    }
+
+    // A directory of its own for each test that writes files.
+    fn temp_dir(name: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("rust-tracer-{name}-{}", std::process::id()));
+        fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    fn render_pixels(file: &SceneFile) -> Vec<[u64; 3]> {
+        let scene = file.build().unwrap();
+        let image = render(
+            &scene.camera,
+            &scene.world,
+            &scene.lights,
+            &scene.settings,
+            &|_, _| {},
+        );
+        image
+            .beauty
+            .pixels
+            .iter()
+            .map(|c| [c.r.to_bits(), c.g.to_bits(), c.b.to_bits()])
+            .collect()
+    }
+
+    #[test]
+    fn saved_scenes_render_the_same() {
+        let dir = temp_dir("save");
+        for name in ["example", "cornell_box"] {
+            let mut file = SceneFile::open(format!("scenes/{name}.toml")).unwrap();
+            file.render.width = 24;
+            file.render.height = 16;
+            file.render.samples_per_pixel = 4;
+
+            let path = dir.join(format!("{name}.toml"));
+            file.save(&path).unwrap();
+            let saved = SceneFile::open(&path).unwrap();
+            assert!(
+                render_pixels(&file) == render_pixels(&saved),
+                "{name} renders differently once saved"
+            );
+        }
+        fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn unknown_materials_report_their_location() {
+        let dir = temp_dir("unknown-material");
+        let path = dir.join("scene.toml");
+        fs::write(
+            &path,
+            format!(
+                "{CAMERA}[materials.m]\ntype = \"lambertian\"\nalbedo = 0.5\n\n\
+                 [[shapes]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\n\
+                 material = \"n\"\n"
+            ),
+        )
+        .unwrap();
+
+        let error = SceneFile::open(&path).unwrap().build().err().unwrap();
+        fs::remove_dir_all(&dir).unwrap();
+        assert_eq!(
+            error.to_string(),
+            format!("{}:10:1: unknown material `n`", path.display())
+        );
+    }
+
+    // Spheres side by side along x, hit head on from above.
+    #[test]
+    fn shapes_naming_a_material_share_its_index() {
+        let source = format!(
+            "{CAMERA}[materials.b]\ntype = \"lambertian\"\nalbedo = 0.5\n\n\
+             [materials.a]\ntype = \"metal\"\nalbedo = 0.5\nfuzz = 0\n\n\
+             [[shapes]]\ntype = \"sphere\"\ncenter = [0, 0, 0]\nradius = 1\nmaterial = \"b\"\n\n\
+             [[shapes]]\ntype = \"sphere\"\ncenter = [3, 0, 0]\nradius = 1\nmaterial = \"a\"\n\n\
+             [[shapes]]\ntype = \"sphere\"\ncenter = [6, 0, 0]\nradius = 1\nmaterial = \"b\"\n\n\
+             [[shapes]]\ntype = \"volume\"\ndensity = 100\nalbedo = [0.5, 0.5, 0.5]\n\
+             boundary = {{ type = \"sphere\", center = [9, 0, 0], radius = 1, material = \"a\" }}\n"
+        );
+        let scene = toml::from_str::<SceneFile>(&source)
+            .unwrap()
+            .build()
+            .unwrap();
+
+        let indices: Vec<_> = [0.0, 3.0, 6.0, 9.0]
+            .into_iter()
+            .map(|x| {
+                let ray = Ray::new(Vector3::new(x, 5.0, 0.0), Vector3::new(0.0, -1.0, 0.0));
+                scene
+                    .world
+                    .hit(&ray, 0.001, f64::INFINITY)
+                    .unwrap()
+                    .material_index
+            })
+            .collect();
+        assert_eq!(indices, [1, 0, 1, 2]);
+    }
+
+    fn settings_error(render: &str) -> String {
+        let source = format!("{CAMERA}[render]\n{render}");
+        let file = toml::from_str::<SceneFile>(&source).unwrap();
+        file.build().err().unwrap().to_string()
+    }
+
+    #[test]
+    fn render_settings_are_checked_before_building() {
+        assert_eq!(
+            settings_error("width = 0\n"),
+            "invalid render settings: `width` must be at least 1"
+        );
+        assert_eq!(
+            settings_error("samples_per_pixel = 0\n"),
+            "invalid render settings: `samples_per_pixel` must be at least 1"
+        );
+        assert_eq!(
+            settings_error("samples_per_pixel = 8\nadaptive = { min_samples = 16 }\n"),
+            "invalid render settings: adaptive `min_samples` (16) is more than \
+             `samples_per_pixel` (8)"
+        );
+    }
+}
@@ -0,0 +1,110 @@
+use std::collections::BTreeMap;
+
+use cgmath::{InnerSpace, Vector3};
+use rand::Rng;
+use toml::Spanned;
+
+use crate::scene::{
+    CameraDescription, MaterialDescription, RenderDescription, SceneFile, ShapeDescription, Vec3,
+};
+
+fn sphere(center: [f64; 3], radius: f64, material: &str) -> Spanned<ShapeDescription> {
+    Spanned::new(
+        0..0,
+        ShapeDescription::Sphere {
+            center,
+            radius,
+            material: material.to_string(),
+        },
+    )
+}
+
+pub fn random_world<R: Rng + ?Sized>(rng: &mut R) -> SceneFile {
+    let mut materials = BTreeMap::new();
+    materials.insert(
+        "ground".to_string(),
+        MaterialDescription::Lambertian {
+            albedo: [0.5, 0.5, 0.5].into(),
+            bump: None,
+        },
+    );
+
+    let mut shapes = vec![sphere([0.0, -1000.0, 0.0], 1000.0, "ground")];
+
+    for a in -11..11 {
+        for b in -11..11 {
+            let center = Vector3::new(
+                a as f64 + 0.9 * rng.gen::<f64>(),
+                0.2,
+                b as f64 + 0.9 * rng.gen::<f64>(),
+            );
+
+            if (center - Vector3::<f64>::new(4.0, 0.2, 0.0)).magnitude() > 0.9 {
+                let choose_mat: f64 = rng.gen();
+                let material = if choose_mat < 0.8 {
+                    MaterialDescription::Lambertian {
+                        albedo: rng.gen::<Vec3>().into(),
+                        bump: None,
+                    }
+                } else if choose_mat < 0.95 {
+                    MaterialDescription::Metal {
+                        albedo: rng.gen::<Vec3>().into(),
+                        fuzz: rng.gen_range(0.0..0.5).into(),
+                        bump: None,
+                    }
+                } else {
+                    MaterialDescription::Dielectric {
+                        index_of_refraction: 1.5.into(),
+                        bump: None,
+                    }
+                };
+
+                let name = format!("sphere_{}", shapes.len());
+                materials.insert(name.clone(), material);
+                shapes.push(sphere(center.into(), 0.2, &name));
+            }
+        }
+    }
+
+    materials.insert(
+        "glass".to_string(),
+        MaterialDescription::Dielectric {
+            index_of_refraction: 1.5.into(),
+            bump: None,
+        },
+    );
+    materials.insert(
+        "matte".to_string(),
+        MaterialDescription::Lambertian {
+            albedo: [0.4, 0.2, 0.1].into(),
+            bump: None,
+        },
+    );
+    materials.insert(
+        "mirror".to_string(),
+        MaterialDescription::Metal {
+            albedo: [0.7, 0.6, 0.5].into(),
+            fuzz: 0.0.into(),
+            bump: None,
+        },
+    );
+
+    shapes.push(sphere([0.0, 1.0, 0.0], 1.0, "glass"));
+    shapes.push(sphere([-4.0, 1.0, 0.0], 1.0, "matte"));
+    shapes.push(sphere([4.0, 1.0, 0.0], 1.0, "mirror"));
+
+    SceneFile {
+        camera: CameraDescription {
+            look_from: [13.0, 2.0, 3.0],
+            look_at: [0.0, 0.0, 0.0],
+            vup: [0.0, 1.0, 0.0],
+            vertical_fov: 20.0,
+            aperture: 0.1,
+            focus_dist: Some(10.0),
+        },
+        render: RenderDescription::default(),
+        materials,
+        shapes,
+        origin: None,
+    }
+}
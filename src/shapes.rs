@@ -1,22 +1,45 @@
-use cgmath::{InnerSpace, Vector3};
-use rand::Rng;
+use std::{f64::consts::PI, sync::Arc};
+
+use cgmath::{InnerSpace, Vector2, Vector3};
 
 use crate::{
+    aabb::Aabb,
     colour::Colour,
-    ray::Ray,
-    vec::{random_in_unit_sphere, random_on_unit_sphere},
+    ray::{Differentials, Ray},
+    sampler::Sampler,
+    texture::{Bump, Texture},
+    vec::sample_unit_sphere,
 };
 
-#[derive(Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Material {
-    Lambetarian { albedo: Colour<f64> },
-    Metal { albedo: Colour<f64>, fuzz: f64 },
-    Dielectric { index_of_refraction: f64 },
+    Lambetarian {
+        albedo: Texture,
+        bump: Option<Bump>,
+    },
+    Metal {
+        albedo: Texture,
+        fuzz: Texture,
+        bump: Option<Bump>,
+    },
+    Dielectric {
+        index_of_refraction: Texture,
+        bump: Option<Bump>,
+    },
+    Isotropic {
+        albedo: Texture,
+    },
+    DiffuseLight {
+        emit: Texture,
+    },
 }
 
 pub struct ScatteredRay {
     pub ray: Ray,
     pub attenuation: Colour<f64>,
+    // The solid angle density the direction was sampled with, or None for
+    // delta lobes such as mirrors and glass that light sampling cannot reach.
+    pub pdf: Option<f64>,
 }
 
 fn almost_zero(vec: Vector3<f64>) -> bool {
@@ -32,6 +55,30 @@ fn reflectance(cosine: f64, ref_idx: f64) -> f64 {
     r0 + (1.0 - r0) * (1.0 - cosine).powi(5)
 }
 
+// Fuzzy metal reflects into a Phong lobe around the mirror direction whose
+// exponent follows from the fuzz the same way OBJ shininess is mapped onto it.
+fn phong_exponent(fuzz: f64) -> Option<f64> {
+    if fuzz < 1e-3 {
+        None
+    } else {
+        Some((2.0 / (fuzz * fuzz) - 2.0).max(0.0))
+    }
+}
+
+fn phong_pdf(exponent: f64, reflected: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+    let cosine = reflected.dot(direction.normalize()).max(0.0);
+    (exponent + 1.0) / (2.0 * PI) * cosine.powf(exponent)
+}
+
+fn sample_phong(exponent: f64, reflected: Vector3<f64>, (s, t): (f64, f64)) -> Vector3<f64> {
+    let cos_theta = s.powf(1.0 / (exponent + 1.0));
+    let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
+    let phi = 2.0 * PI * t;
+
+    let (u, v) = orthonormal_basis(reflected);
+    sin_theta * phi.cos() * u + sin_theta * phi.sin() * v + cos_theta * reflected
+}
+
 fn refract(incident: Vector3<f64>, normal: Vector3<f64>, etia_over_etat: f64) -> Vector3<f64> {
     let cos_theta = -incident.dot(normal).min(1.0);
     let r_out_perp = etia_over_etat * (incident + cos_theta * normal);
@@ -39,31 +86,96 @@ fn refract(incident: Vector3<f64>, normal: Vector3<f64>, etia_over_etat: f64) ->
     r_out_parallel + r_out_perp
 }
 
+// The differentials of a ray arriving along `ray` and leaving along the unit
+// direction `scattered`, which is its mirror image about the normal if `eta`
+// is None or refracted with that ratio of indices otherwise. Both follow from
+// differentiating the reflection and refraction formulas, as in pbrt.
+fn specular_differentials(
+    ray: &Ray,
+    hit_record: &HitRecord,
+    scattered: Vector3<f64>,
+    eta: Option<f64>,
+) -> Option<Differentials> {
+    let differentials = ray.differentials?;
+    let footprint = hit_record.footprint?;
+    let n = hit_record.normal;
+    let wo = -ray.direction.normalize();
+    let wo_n = wo.dot(n);
+
+    let direction = |offset_direction: Vector3<f64>, dndx: Vector3<f64>| {
+        let dwodx = -offset_direction.normalize() - wo;
+        let d_wo_n = dwodx.dot(n) + wo.dot(dndx);
+        match eta {
+            None => scattered - dwodx + 2.0 * (wo_n * dndx + d_wo_n * n),
+            Some(eta) => {
+                let wi_n = scattered.dot(n).abs();
+                let mu = eta * wo_n - wi_n;
+                let dmudx = (eta - eta * eta * wo_n / wi_n) * d_wo_n;
+                scattered - eta * dwodx + (mu * dndx + dmudx * n)
+            }
+        }
+    };
+
+    Some(Differentials {
+        rx_origin: hit_record.p + footprint.dpdx,
+        rx_direction: direction(differentials.rx_direction, footprint.dndx),
+        ry_origin: hit_record.p + footprint.dpdy,
+        ry_direction: direction(differentials.ry_direction, footprint.dndy),
+    })
+}
+
 impl Material {
-    pub fn scatter(self, ray: &Ray, hit_record: &HitRecord) -> Option<ScatteredRay> {
+    pub fn scatter<S: Sampler + ?Sized>(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        sampler: &mut S,
+    ) -> Option<ScatteredRay> {
         match self {
-            Material::Lambetarian { albedo } => {
-                let mut scatter_direction = hit_record.normal + random_on_unit_sphere();
+            Material::Lambetarian { albedo, .. } => {
+                let mut scatter_direction =
+                    hit_record.normal + sample_unit_sphere(sampler.get_2d());
 
                 if almost_zero(scatter_direction) {
                     scatter_direction = hit_record.normal;
                 }
 
+                let cosine = hit_record.normal.dot(scatter_direction.normalize());
                 Some(ScatteredRay {
                     ray: Ray::new(hit_record.p, scatter_direction),
-                    attenuation: albedo,
+                    attenuation: albedo.value(hit_record),
+                    pdf: Some(cosine.max(0.0) / PI),
                 })
             }
-            Material::Metal { albedo, fuzz } => {
+            Material::Metal { albedo, fuzz, .. } => {
                 let reflected = reflect(ray.direction.normalize(), hit_record.normal);
+                let Some(exponent) = phong_exponent(fuzz.scalar(hit_record)) else {
+                    return Some(ScatteredRay {
+                        ray: Ray {
+                            differentials: specular_differentials(ray, hit_record, reflected, None),
+                            ..Ray::new(hit_record.p, reflected)
+                        },
+                        attenuation: albedo.value(hit_record),
+                        pdf: None,
+                    });
+                };
+
+                let direction = sample_phong(exponent, reflected, sampler.get_2d());
+                if direction.dot(hit_record.normal) <= 0.0 {
+                    return None;
+                }
+
                 Some(ScatteredRay {
-                    ray: Ray::new(hit_record.p, reflected + fuzz * random_in_unit_sphere()),
-                    attenuation: albedo,
+                    ray: Ray::new(hit_record.p, direction),
+                    attenuation: albedo.value(hit_record),
+                    pdf: Some(phong_pdf(exponent, reflected, direction)),
                 })
             }
             Material::Dielectric {
                 index_of_refraction,
+                ..
             } => {
+                let index_of_refraction = index_of_refraction.scalar(hit_record);
                 let refraction_ratio = if hit_record.front_face {
                     1.0 / index_of_refraction
                 } else {
@@ -75,38 +187,234 @@ impl Material {
                 let sin_theta = (1.0 - cos_theta * cos_theta).sqrt();
 
                 let cannot_refract = refraction_ratio * sin_theta > 1.0;
-                let direction = if cannot_refract
-                    || reflectance(cos_theta, refraction_ratio) > rand::thread_rng().gen()
+                let u = sampler.get_1d();
+                let (direction, eta) = if cannot_refract
+                    || reflectance(cos_theta, refraction_ratio) > u
                 {
-                    reflect(unit_direction, hit_record.normal)
+                    (reflect(unit_direction, hit_record.normal), None)
                 } else {
-                    refract(unit_direction, hit_record.normal, refraction_ratio)
+                    let direction = refract(unit_direction, hit_record.normal, refraction_ratio);
+                    (direction, Some(refraction_ratio))
                 };
 
                 Some(ScatteredRay {
-                    ray: Ray::new(hit_record.p, direction),
+                    ray: Ray {
+                        differentials: specular_differentials(ray, hit_record, direction, eta),
+                        ..Ray::new(hit_record.p, direction)
+                    },
                     attenuation: Colour::new(1.0, 1.0, 1.0),
+                    pdf: None,
                 })
             }
+            Material::Isotropic { albedo } => Some(ScatteredRay {
+                ray: Ray::new(hit_record.p, sample_unit_sphere(sampler.get_2d())),
+                attenuation: albedo.value(hit_record),
+                pdf: Some(1.0 / (4.0 * PI)),
+            }),
+            Material::DiffuseLight { .. } => None,
+        }
+    }
+
+    // Whether the material scatters light diffusely rather than reflecting or
+    // refracting it like metal and glass.
+    pub fn is_diffuse(&self) -> bool {
+        matches!(
+            self,
+            Material::Lambetarian { .. } | Material::Isotropic { .. }
+        )
+    }
+
+    // Whether every direction `scatter` can pick has zero probability of being
+    // found by light sampling, so the two strategies cannot be combined.
+    pub fn is_delta(&self, hit_record: &HitRecord) -> bool {
+        match self {
+            Material::Metal { fuzz, .. } => phong_exponent(fuzz.scalar(hit_record)).is_none(),
+            Material::Dielectric { .. } | Material::DiffuseLight { .. } => true,
+            Material::Lambetarian { .. } | Material::Isotropic { .. } => false,
+        }
+    }
+
+    // The density with which `scatter` picks `direction` for a ray arriving
+    // along `ray`; zero for delta lobes.
+    pub fn scattering_pdf(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        direction: Vector3<f64>,
+    ) -> f64 {
+        match self {
+            Material::Lambetarian { .. } => {
+                hit_record.normal.dot(direction.normalize()).max(0.0) / PI
+            }
+            Material::Metal { fuzz, .. } => match phong_exponent(fuzz.scalar(hit_record)) {
+                Some(exponent) if direction.dot(hit_record.normal) > 0.0 => {
+                    let reflected = reflect(ray.direction.normalize(), hit_record.normal);
+                    phong_pdf(exponent, reflected, direction)
+                }
+                _ => 0.0,
+            },
+            Material::Isotropic { .. } => 1.0 / (4.0 * PI),
+            Material::Dielectric { .. } | Material::DiffuseLight { .. } => 0.0,
+        }
+    }
+
+    // The BSDF times the cosine term for light arriving from `direction`. Every
+    // material importance samples itself exactly, so this is the attenuation
+    // of `scatter` times its pdf.
+    pub fn scattering(
+        &self,
+        ray: &Ray,
+        hit_record: &HitRecord,
+        direction: Vector3<f64>,
+    ) -> Colour<f64> {
+        let pdf = self.scattering_pdf(ray, hit_record, direction);
+        match self {
+            Material::Lambetarian { albedo, .. }
+            | Material::Metal { albedo, .. }
+            | Material::Isotropic { albedo } => albedo.value(hit_record) * pdf,
+            Material::Dielectric { .. } | Material::DiffuseLight { .. } => {
+                Colour::new(0.0, 0.0, 0.0)
+            }
+        }
+    }
+
+    // The colour the surface reflects, as seen by the albedo debug view.
+    pub fn albedo(&self, hit_record: &HitRecord) -> Colour<f64> {
+        match self {
+            Material::Lambetarian { albedo, .. }
+            | Material::Metal { albedo, .. }
+            | Material::Isotropic { albedo } => albedo.value(hit_record),
+            Material::Dielectric { .. } => Colour::new(1.0, 1.0, 1.0),
+            Material::DiffuseLight { emit } => {
+                let emit = emit.value(hit_record);
+                Colour::new(emit.r.min(1.0), emit.g.min(1.0), emit.b.min(1.0))
+            }
+        }
+    }
+
+    pub fn bump(&self) -> Option<&Bump> {
+        match self {
+            Material::Lambetarian { bump, .. }
+            | Material::Metal { bump, .. }
+            | Material::Dielectric { bump, .. } => bump.as_ref(),
+            Material::Isotropic { .. } | Material::DiffuseLight { .. } => None,
+        }
+    }
+
+    // Lights only emit from the side their normal faces.
+    pub fn emitted(&self, hit_record: &HitRecord) -> Colour<f64> {
+        match self {
+            Material::DiffuseLight { emit } if hit_record.front_face => emit.value(hit_record),
+            _ => Colour::new(0.0, 0.0, 0.0),
+        }
+    }
+}
+
+// How the position and normal of a surface change with its texture
+// coordinates, from which ray differentials give texture footprints.
+#[derive(Debug, Clone, Copy)]
+pub struct Tangents {
+    pub dpdu: Vector3<f64>,
+    pub dpdv: Vector3<f64>,
+    pub dndu: Vector3<f64>,
+    pub dndv: Vector3<f64>,
+}
+
+impl Tangents {
+    pub fn flat(dpdu: Vector3<f64>, dpdv: Vector3<f64>) -> Self {
+        let zero = Vector3::new(0.0, 0.0, 0.0);
+        Tangents {
+            dpdu,
+            dpdv,
+            dndu: zero,
+            dndv: zero,
         }
     }
 }
 
-pub struct HitRecord {
+// How far the hit point, its normal and its texture coordinates move from one
+// pixel to the next.
+#[derive(Debug, Clone, Copy)]
+pub struct Footprint {
+    pub dpdx: Vector3<f64>,
+    pub dpdy: Vector3<f64>,
+    pub dndx: Vector3<f64>,
+    pub dndy: Vector3<f64>,
+    pub duvdx: Vector2<f64>,
+    pub duvdy: Vector2<f64>,
+}
+
+impl Footprint {
+    // The offset rays are intersected with the tangent plane at `p`, and the
+    // texture coordinate changes that best explain where they land are found
+    // by least squares.
+    fn new(
+        differentials: &Differentials,
+        p: Vector3<f64>,
+        normal: Vector3<f64>,
+        tangents: &Tangents,
+    ) -> Option<Self> {
+        let offset = |origin: Vector3<f64>, direction: Vector3<f64>| {
+            let t = (p - origin).dot(normal) / direction.dot(normal);
+            t.is_finite().then(|| origin + t * direction - p)
+        };
+        let dpdx = offset(differentials.rx_origin, differentials.rx_direction)?;
+        let dpdy = offset(differentials.ry_origin, differentials.ry_direction)?;
+
+        let Tangents {
+            dpdu,
+            dpdv,
+            dndu,
+            dndv,
+        } = *tangents;
+        let (a, b, c) = (dpdu.magnitude2(), dpdu.dot(dpdv), dpdv.magnitude2());
+        let det = a * c - b * b;
+        let solve = |dp: Vector3<f64>| {
+            if det > 1e-12 * a * c {
+                let (r0, r1) = (dpdu.dot(dp), dpdv.dot(dp));
+                Vector2::new((c * r0 - b * r1) / det, (a * r1 - b * r0) / det)
+            } else {
+                Vector2::new(0.0, 0.0)
+            }
+        };
+        let (duvdx, duvdy) = (solve(dpdx), solve(dpdy));
+
+        Some(Footprint {
+            dpdx,
+            dpdy,
+            dndx: duvdx.x * dndu + duvdx.y * dndv,
+            dndy: duvdy.x * dndu + duvdy.y * dndv,
+            duvdx,
+            duvdy,
+        })
+    }
+}
+
+pub struct HitRecord<'a> {
     pub p: Vector3<f64>,
     pub normal: Vector3<f64>,
     pub t: f64,
     pub front_face: bool,
-    pub material: Material,
+    pub uv: Vector2<f64>,
+    // Only for rays that carry differentials.
+    pub footprint: Option<Footprint>,
+    pub material: &'a Material,
+    // The index of the scene object that was hit and of the primitive, such
+    // as a mesh triangle, within it.
+    pub object: usize,
+    pub primitive: usize,
+    // The index of the material, shared by every shape that names it.
+    pub material_index: usize,
 }
 
-impl HitRecord {
-    fn new(
+impl<'a> HitRecord<'a> {
+    pub(crate) fn new(
         t: f64,
         p: Vector3<f64>,
         outward_normal: Vector3<f64>,
+        uv: Vector2<f64>,
         ray: &Ray,
-        material: Material,
+        material: &'a Material,
     ) -> Self {
         let front_face = ray.direction.dot(outward_normal) < 0.0;
 
@@ -119,26 +427,97 @@ impl HitRecord {
             },
             t,
             front_face,
+            uv,
+            footprint: None,
             material,
+            object: 0,
+            primitive: 0,
+            material_index: 0,
+        }
+    }
+
+    // Finds the footprint of the ray's differentials before the normal is
+    // changed from the geometric one. `tangents` are for the outward normal,
+    // and only worked out when the ray has differentials.
+    pub(crate) fn with_footprint(mut self, ray: &Ray, tangents: impl FnOnce() -> Tangents) -> Self {
+        if let Some(differentials) = &ray.differentials {
+            let mut tangents = tangents();
+            if !self.front_face {
+                tangents.dndu = -tangents.dndu;
+                tangents.dndv = -tangents.dndv;
+            }
+            self.footprint = Footprint::new(differentials, self.p, self.normal, &tangents);
+        }
+        self
+    }
+
+    // Bumps the shading normal if the material has a bump map. Shapes call
+    // this last, once the normal they shade with is settled.
+    pub(crate) fn with_bump(mut self) -> Self {
+        if let Some(bump) = self.material.bump() {
+            self.normal = bump.normal(&self);
         }
+        self
     }
 }
 
-pub trait Hittable {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+pub trait Hittable: Send + Sync {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>>;
+
+    fn bounding_box(&self) -> Option<Aabb>;
+
+    // The solid angle density with which `random` picks `direction` from
+    // `origin`; zero for shapes that cannot be sampled as lights.
+    fn pdf_value(&self, _origin: Vector3<f64>, _direction: Vector3<f64>) -> f64 {
+        0.0
+    }
+
+    // A direction from `origin` towards a random point on the shape.
+    fn random(&self, _origin: Vector3<f64>, _sampler: &mut dyn Sampler) -> Vector3<f64> {
+        Vector3::unit_x()
+    }
 }
 
+impl<T: Hittable + ?Sized> Hittable for Arc<T> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        (**self).hit(ray, t_min, t_max)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        (**self).bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        (**self).pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        (**self).random(origin, sampler)
+    }
+}
+
+#[derive(Default)]
 pub struct World {
-    pub shapes: Vec<Sphere>,
+    pub objects: Vec<Arc<dyn Hittable>>,
+}
+
+impl World {
+    pub fn new() -> Self {
+        World::default()
+    }
+
+    pub fn add<T: Hittable + 'static>(&mut self, object: T) {
+        self.objects.push(Arc::new(object));
+    }
 }
 
 impl Hittable for World {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let mut closest_so_far = t_max;
         let mut record: Option<HitRecord> = None;
 
-        for shape in self.shapes.iter() {
-            if let Some(hit) = shape.hit(ray, t_min, closest_so_far) {
+        for object in self.objects.iter() {
+            if let Some(hit) = object.hit(ray, t_min, closest_so_far) {
                 closest_so_far = hit.t;
                 record = Some(hit);
             };
@@ -146,6 +525,89 @@ impl Hittable for World {
 
         record
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.objects
+            .iter()
+            .try_fold(Aabb::empty(), |bounds, object| {
+                Some(bounds.union(&object.bounding_box()?))
+            })
+    }
+
+    // Sampling picks one of the objects uniformly.
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        if self.objects.is_empty() {
+            return 0.0;
+        }
+
+        let sum: f64 = self
+            .objects
+            .iter()
+            .map(|object| object.pdf_value(origin, direction))
+            .sum();
+        sum / self.objects.len() as f64
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        if self.objects.is_empty() {
+            return Vector3::unit_x();
+        }
+
+        let index = pick(self.objects.len(), sampler.get_1d());
+        self.objects[index].random(origin, sampler)
+    }
+}
+
+// Tags every hit on a scene object with the object's index.
+pub struct Indexed {
+    pub object: Arc<dyn Hittable>,
+    pub index: usize,
+}
+
+impl Hittable for Indexed {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut record = self.object.hit(ray, t_min, t_max)?;
+        record.object = self.index;
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        self.object.random(origin, sampler)
+    }
+}
+
+// Tags every hit on a shape with the index of its material.
+pub struct MaterialIndexed {
+    pub object: Arc<dyn Hittable>,
+    pub index: usize,
+}
+
+impl Hittable for MaterialIndexed {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut record = self.object.hit(ray, t_min, t_max)?;
+        record.material_index = self.index;
+        Some(record)
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.object.bounding_box()
+    }
+
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        self.object.pdf_value(origin, direction)
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        self.object.random(origin, sampler)
+    }
 }
 
 pub struct Sphere {
@@ -155,7 +617,7 @@ pub struct Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
         let oc = ray.origin - self.center;
         let a = ray.direction.magnitude2();
         let half_b = oc.dot(ray.direction);
@@ -175,9 +637,271 @@ impl Hittable for Sphere {
             }
 
             let p = ray.at(root);
-            let outward_normal = (p - self.center) / self.radius;
+            let n = (p - self.center) / self.radius;
+            let uv = Vector2::new(((-n.z).atan2(n.x) + PI) / (2.0 * PI), (-n.y).acos() / PI);
+
+            // u follows longitude and v latitude, with sin(theta) kept from
+            // zero at the poles where longitude is undefined.
+            let tangents = || {
+                let sin_theta = (n.x * n.x + n.z * n.z).sqrt().max(1e-12);
+                let dndu = 2.0 * PI * Vector3::new(n.z, 0.0, -n.x);
+                let dndv =
+                    PI * Vector3::new(-n.y * n.x / sin_theta, sin_theta, -n.y * n.z / sin_theta);
+                Tangents {
+                    dpdu: self.radius * dndu,
+                    dpdv: self.radius * dndv,
+                    dndu,
+                    dndv,
+                }
+            };
 
-            Some(HitRecord::new(root, p, outward_normal, ray, self.material))
+            Some(
+                HitRecord::new(root, p, n, uv, ray, &self.material)
+                    .with_footprint(ray, tangents)
+                    .with_bump(),
+            )
         }
     }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let r = Vector3::new(self.radius, self.radius, self.radius);
+        Some(Aabb::new(self.center - r, self.center + r))
+    }
+
+    // Samples the cone of directions the sphere subtends from outside.
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let distance_squared = (self.center - origin).magnitude2();
+        if distance_squared <= self.radius * self.radius
+            || self
+                .hit(&Ray::new(origin, direction), 0.001, f64::INFINITY)
+                .is_none()
+        {
+            return 0.0;
+        }
+
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared).sqrt();
+        1.0 / (2.0 * PI * (1.0 - cos_theta_max))
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let direction = self.center - origin;
+        let distance_squared = direction.magnitude2();
+        let cos_theta_max = (1.0 - self.radius * self.radius / distance_squared)
+            .max(0.0)
+            .sqrt();
+
+        let (s, t) = sampler.get_2d();
+        let phi = 2.0 * PI * s;
+        let z = 1.0 + t * (cos_theta_max - 1.0);
+        let r = (1.0 - z * z).sqrt();
+
+        let w = direction.normalize();
+        let (u, v) = orthonormal_basis(w);
+        r * phi.cos() * u + r * phi.sin() * v + z * w
+    }
+}
+
+pub struct Plane {
+    pub point: Vector3<f64>,
+    pub normal: Vector3<f64>,
+    pub material: Material,
+}
+
+fn orthonormal_basis(n: Vector3<f64>) -> (Vector3<f64>, Vector3<f64>) {
+    let a = if n.x.abs() > 0.9 {
+        Vector3::unit_y()
+    } else {
+        Vector3::unit_x()
+    };
+    let v = n.cross(a).normalize();
+    let u = n.cross(v);
+    (u, v)
+}
+
+// Which of `count` items a uniform sample `u` falls on.
+fn pick(count: usize, u: f64) -> usize {
+    ((u * count as f64) as usize).min(count - 1)
+}
+
+impl Hittable for Plane {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let normal = self.normal.normalize();
+        let denominator = normal.dot(ray.direction);
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.point - ray.origin).dot(normal) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let p = ray.at(t);
+        let (u, v) = orthonormal_basis(normal);
+        let uv = Vector2::new((p - self.point).dot(u), (p - self.point).dot(v));
+
+        Some(
+            HitRecord::new(t, p, normal, uv, ray, &self.material)
+                .with_footprint(ray, || Tangents::flat(u, v))
+                .with_bump(),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        None
+    }
+}
+
+pub struct Quad {
+    pub corner: Vector3<f64>,
+    pub u: Vector3<f64>,
+    pub v: Vector3<f64>,
+    pub material: Material,
+}
+
+impl Hittable for Quad {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let n = self.u.cross(self.v);
+        let normal = n.normalize();
+        let denominator = normal.dot(ray.direction);
+        if denominator.abs() < 1e-8 {
+            return None;
+        }
+
+        let t = (self.corner - ray.origin).dot(normal) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+
+        let p = ray.at(t);
+        let w = n / n.magnitude2();
+        let planar = p - self.corner;
+        let alpha = w.dot(planar.cross(self.v));
+        let beta = w.dot(self.u.cross(planar));
+
+        if !(0.0..=1.0).contains(&alpha) || !(0.0..=1.0).contains(&beta) {
+            return None;
+        }
+
+        Some(
+            HitRecord::new(t, p, normal, Vector2::new(alpha, beta), ray, &self.material)
+                .with_footprint(ray, || Tangents::flat(self.u, self.v))
+                .with_bump(),
+        )
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        Some(
+            Aabb::from_points([
+                self.corner,
+                self.corner + self.u,
+                self.corner + self.v,
+                self.corner + self.u + self.v,
+            ])
+            .padded(),
+        )
+    }
+
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let Some(hit) = self.hit(&Ray::new(origin, direction), 0.001, f64::INFINITY) else {
+            return 0.0;
+        };
+
+        let area = self.u.cross(self.v).magnitude();
+        let distance_squared = hit.t * hit.t * direction.magnitude2();
+        let cosine = direction.dot(hit.normal).abs() / direction.magnitude();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let (s, t) = sampler.get_2d();
+        self.corner + s * self.u + t * self.v - origin
+    }
+}
+
+pub struct Cuboid {
+    faces: [Quad; 6],
+}
+
+impl Cuboid {
+    pub fn new(a: Vector3<f64>, b: Vector3<f64>, material: Material) -> Self {
+        let min = Vector3::new(a.x.min(b.x), a.y.min(b.y), a.z.min(b.z));
+        let max = Vector3::new(a.x.max(b.x), a.y.max(b.y), a.z.max(b.z));
+
+        let dx = Vector3::new(max.x - min.x, 0.0, 0.0);
+        let dy = Vector3::new(0.0, max.y - min.y, 0.0);
+        let dz = Vector3::new(0.0, 0.0, max.z - min.z);
+
+        let quad = |corner, u, v| Quad {
+            corner,
+            u,
+            v,
+            material: material.clone(),
+        };
+
+        Cuboid {
+            faces: [
+                quad(Vector3::new(min.x, min.y, max.z), dx, dy),
+                quad(Vector3::new(max.x, min.y, max.z), -dz, dy),
+                quad(Vector3::new(max.x, min.y, min.z), -dx, dy),
+                quad(Vector3::new(min.x, min.y, min.z), dz, dy),
+                quad(Vector3::new(min.x, max.y, max.z), dx, -dz),
+                quad(Vector3::new(min.x, min.y, min.z), dx, dz),
+            ],
+        }
+    }
+}
+
+impl Hittable for Cuboid {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let mut closest_so_far = t_max;
+        let mut record: Option<HitRecord> = None;
+
+        for face in self.faces.iter() {
+            if let Some(hit) = face.hit(ray, t_min, closest_so_far) {
+                closest_so_far = hit.t;
+                record = Some(hit);
+            };
+        }
+
+        record
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        let faces = self.faces.iter().filter_map(|face| face.bounding_box());
+        Some(faces.fold(Aabb::empty(), |bounds, face| bounds.union(&face)))
+    }
+
+    fn pdf_value(&self, origin: Vector3<f64>, direction: Vector3<f64>) -> f64 {
+        let sum: f64 = self
+            .faces
+            .iter()
+            .map(|face| face.pdf_value(origin, direction))
+            .sum();
+        sum / self.faces.len() as f64
+    }
+
+    fn random(&self, origin: Vector3<f64>, sampler: &mut dyn Sampler) -> Vector3<f64> {
+        let index = pick(self.faces.len(), sampler.get_1d());
+        self.faces[index].random(origin, sampler)
+    }
+}
+
+// Checks that `object` seen from `origin` is sampled with a density that
+// integrates to one over the sphere of directions, and that the directions
+// `random` picks are ones the density covers.
+#[cfg(test)]
+pub(crate) fn assert_pdf_normalised(object: &dyn Hittable, origin: Vector3<f64>) {
+    let mut sampler = crate::sampler::IndependentSampler::new(1);
+    let count = 200_000;
+    let integral: f64 = (0..count)
+        .map(|_| object.pdf_value(origin, sample_unit_sphere(sampler.get_2d())) * 4.0 * PI)
+        .sum::<f64>()
+        / count as f64;
+    assert!((integral - 1.0).abs() < 0.02, "{integral}");
+
+    for _ in 0..1000 {
+        let direction = object.random(origin, &mut sampler);
+        assert!(object.pdf_value(origin, direction) > 0.0);
+    }
 }
@@ -0,0 +1,604 @@
+use std::{
+    cell::RefCell,
+    collections::HashMap,
+    fmt, io,
+    path::{Path, PathBuf},
+    sync::Arc,
+};
+
+use cgmath::{InnerSpace, Vector2, Vector3, Zero};
+use serde::{Deserialize, Serialize};
+
+use crate::{
+    colour::Colour,
+    input::read_image,
+    noise::{granite, marble, octaves_for, turbulence, wood},
+    output::Framebuffer,
+    shapes::HitRecord,
+};
+
+// A material parameter that varies over surfaces, looked up from the texture
+// coordinates and the position of a hit. Parameters that are numbers, like
+// fuzz, take the mean of the channels.
+#[derive(Debug, Clone)]
+pub enum Texture {
+    Solid(Colour<f64>),
+    // Alternating cubes `scale` wide, or squares of texture space with `uv`,
+    // averaged over the footprint of the hit.
+    Checker {
+        scale: f64,
+        uv: bool,
+        even: Box<Texture>,
+        odd: Box<Texture>,
+    },
+    // From `from` at v = 0 to `to` at v = 1.
+    Gradient {
+        from: Colour<f64>,
+        to: Colour<f64>,
+    },
+    Image {
+        image: Arc<ImageTexture>,
+        filter: ImageFilter,
+        wrap: WrapMode,
+    },
+    // Marble-like veins of turbulence across z, `scale` of them per unit.
+    Noise {
+        scale: f64,
+    },
+    // Solid textures, which blend from `from` to `to` by a pattern of noise
+    // whose features are `scale` wide. Marble has veins across x that
+    // `turbulence` pushes around by about that many veins, wood has growth
+    // rings around the y axis made as irregular, and granite has crystals
+    // with darker seams between them.
+    Marble {
+        scale: f64,
+        turbulence: f64,
+        from: Box<Texture>,
+        to: Box<Texture>,
+    },
+    Wood {
+        scale: f64,
+        turbulence: f64,
+        from: Box<Texture>,
+        to: Box<Texture>,
+    },
+    Granite {
+        scale: f64,
+        from: Box<Texture>,
+        to: Box<Texture>,
+    },
+}
+
+// The most octaves of noise the solid textures add up.
+const MAX_OCTAVES: f64 = 8.0;
+
+impl Texture {
+    pub fn value(&self, hit_record: &HitRecord) -> Colour<f64> {
+        let (uv, p, footprint) = (hit_record.uv, hit_record.p, hit_record.footprint);
+        match self {
+            Texture::Solid(colour) => *colour,
+            Texture::Checker {
+                scale,
+                uv: in_uv,
+                even,
+                odd,
+            } => {
+                let odd_fraction = if *in_uv {
+                    let (dx, dy) = footprint
+                        .map_or((Vector2::zero(), Vector2::zero()), |f| (f.duvdx, f.duvdy));
+                    checker_odd_fraction(&[
+                        (uv.x / scale, dx.x.abs().max(dy.x.abs()) / scale),
+                        (uv.y / scale, dx.y.abs().max(dy.y.abs()) / scale),
+                    ])
+                } else {
+                    let (dx, dy) =
+                        footprint.map_or((Vector3::zero(), Vector3::zero()), |f| (f.dpdx, f.dpdy));
+                    checker_odd_fraction(&[
+                        (p.x / scale, dx.x.abs().max(dy.x.abs()) / scale),
+                        (p.y / scale, dx.y.abs().max(dy.y.abs()) / scale),
+                        (p.z / scale, dx.z.abs().max(dy.z.abs()) / scale),
+                    ])
+                };
+                mix(even, odd, odd_fraction, hit_record)
+            }
+            Texture::Gradient { from, to } => {
+                let t = uv.y.clamp(0.0, 1.0);
+                *from * (1.0 - t) + *to * t
+            }
+            Texture::Image {
+                image,
+                filter,
+                wrap,
+            } => {
+                let (duvdx, duvdy) =
+                    footprint.map_or((Vector2::zero(), Vector2::zero()), |f| (f.duvdx, f.duvdy));
+                image.sample(uv, duvdx, duvdy, *filter, *wrap)
+            }
+            Texture::Noise { scale } => {
+                let v = 0.5 * (1.0 + (scale * p.z + 10.0 * turbulence(p, 7.0)).sin());
+                Colour::new(v, v, v)
+            }
+            Texture::Marble {
+                scale,
+                turbulence,
+                from,
+                to,
+            } => {
+                let (p, octaves) = solid_point(hit_record, *scale);
+                mix(from, to, marble(p, *turbulence, octaves), hit_record)
+            }
+            Texture::Wood {
+                scale,
+                turbulence,
+                from,
+                to,
+            } => {
+                let (p, octaves) = solid_point(hit_record, *scale);
+                mix(from, to, wood(p, *turbulence, octaves), hit_record)
+            }
+            Texture::Granite { scale, from, to } => {
+                let (p, octaves) = solid_point(hit_record, *scale);
+                mix(from, to, granite(p, octaves), hit_record)
+            }
+        }
+    }
+
+    pub fn scalar(&self, hit_record: &HitRecord) -> f64 {
+        let c = self.value(hit_record);
+        (c.r + c.g + c.b) / 3.0
+    }
+}
+
+// `from` blended `t` of the way to `to`, only looking up the ones it needs.
+fn mix(from: &Texture, to: &Texture, t: f64, hit_record: &HitRecord) -> Colour<f64> {
+    if t <= 0.0 {
+        from.value(hit_record)
+    } else if t >= 1.0 {
+        to.value(hit_record)
+    } else {
+        from.value(hit_record) * (1.0 - t) + to.value(hit_record) * t
+    }
+}
+
+// The position of a hit in units of `scale`, and the octaves of noise to add
+// there before they get finer than its footprint.
+fn solid_point(hit_record: &HitRecord, scale: f64) -> (Vector3<f64>, f64) {
+    let width = hit_record
+        .footprint
+        .map_or(0.0, |f| f.dpdx.magnitude().max(f.dpdy.magnitude()) / scale);
+    (hit_record.p / scale, octaves_for(width, MAX_OCTAVES))
+}
+
+// The integral of a function that is 1 where floor(x) is odd and 0 elsewhere.
+fn odd_cells_integral(x: f64) -> f64 {
+    let half = x / 2.0;
+    half.floor() + 2.0 * (half - half.floor() - 0.5).max(0.0)
+}
+
+// How much of a box around a point falls in odd checker cells, given the
+// position and half-width of the box along each axis in cells. The parity of
+// the cell sum is odd when an odd number of axes are, which for independent
+// axes with odd fractions f is (1 - prod(1 - 2f)) / 2.
+fn checker_odd_fraction(axes: &[(f64, f64)]) -> f64 {
+    let even_minus_odd: f64 = axes
+        .iter()
+        .map(|&(x, half_width)| {
+            let odd = if half_width > 0.0 {
+                (odd_cells_integral(x + half_width) - odd_cells_integral(x - half_width))
+                    / (2.0 * half_width)
+            } else {
+                x.floor().rem_euclid(2.0)
+            };
+            1.0 - 2.0 * odd
+        })
+        .product();
+    (1.0 - even_minus_odd) / 2.0
+}
+
+// Perturbs the shading normal as if the surface were displaced along it by
+// `height` times `scale`. The slope comes from how the height changes with
+// position, so solid textures bump any surface while ones that only vary
+// across texture space, such as images, leave it smooth.
+#[derive(Debug, Clone)]
+pub struct Bump {
+    pub height: Texture,
+    pub scale: f64,
+}
+
+impl Bump {
+    pub fn normal(&self, hit_record: &HitRecord) -> Vector3<f64> {
+        // Differences over the footprint also smooth away bumps finer than a
+        // pixel.
+        let delta = hit_record.footprint.map_or(1e-4, |f| {
+            (0.5 * (f.dpdx.magnitude() + f.dpdy.magnitude())).max(1e-6)
+        });
+        let height = |p: Vector3<f64>| self.height.scalar(&HitRecord { p, ..*hit_record });
+
+        let p = hit_record.p;
+        let h = height(p);
+        let slope = Vector3::new(
+            height(p + Vector3::unit_x() * delta) - h,
+            height(p + Vector3::unit_y() * delta) - h,
+            height(p + Vector3::unit_z() * delta) - h,
+        ) * (self.scale / delta);
+
+        // The normal of the displaced surface tilts away from the uphill
+        // direction along the surface. Back faces have the outward normal
+        // flipped, and so the tilt too.
+        let n = hit_record.normal;
+        let along_surface = slope - slope.dot(n) * n;
+        if hit_record.front_face {
+            (n - along_surface).normalize()
+        } else {
+            (n + along_surface).normalize()
+        }
+    }
+}
+
+impl From<Colour<f64>> for Texture {
+    fn from(colour: Colour<f64>) -> Self {
+        Texture::Solid(colour)
+    }
+}
+
+impl From<f64> for Texture {
+    fn from(value: f64) -> Self {
+        Texture::Solid(Colour::new(value, value, value))
+    }
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ImageFilter {
+    Nearest,
+    Bilinear,
+    // Bilinear lookups on the two MIP levels nearest the footprint, blended.
+    #[default]
+    Trilinear,
+    // Elliptically weighted averages over the footprint, which keep detail
+    // along surfaces seen at grazing angles that trilinear lookups blur.
+    Ewa,
+}
+
+// How texture coordinates outside [0, 1] address the image.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum WrapMode {
+    #[default]
+    Repeat,
+    Clamp,
+    Mirror,
+}
+
+impl WrapMode {
+    // The texel that index `i` picks from a row or column of `n`.
+    fn apply(self, i: i64, n: u32) -> usize {
+        let n = n as i64;
+        let i = match self {
+            WrapMode::Repeat => i.rem_euclid(n),
+            WrapMode::Clamp => i.clamp(0, n - 1),
+            WrapMode::Mirror => {
+                let i = i.rem_euclid(2 * n);
+                if i < n {
+                    i
+                } else {
+                    2 * n - 1 - i
+                }
+            }
+        };
+        i as usize
+    }
+}
+
+struct Level {
+    width: u32,
+    height: u32,
+    texels: Vec<Colour<f32>>,
+}
+
+impl Level {
+    fn texel(&self, x: i64, y: i64, wrap: WrapMode) -> Colour<f64> {
+        let (x, y) = (wrap.apply(x, self.width), wrap.apply(y, self.height));
+        let c = self.texels[y * self.width as usize + x];
+        Colour::new(c.r as f64, c.g as f64, c.b as f64)
+    }
+
+    // Texture space has v = 0 at the bottom row, and texel centres sit at
+    // half-integer positions.
+    fn position(&self, uv: Vector2<f64>) -> (f64, f64) {
+        (uv.x * self.width as f64, (1.0 - uv.y) * self.height as f64)
+    }
+
+    fn nearest(&self, uv: Vector2<f64>, wrap: WrapMode) -> Colour<f64> {
+        let (x, y) = self.position(uv);
+        self.texel(x.floor() as i64, y.floor() as i64, wrap)
+    }
+
+    fn bilinear(&self, uv: Vector2<f64>, wrap: WrapMode) -> Colour<f64> {
+        let (x, y) = self.position(uv);
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (x0, y0) = (x.floor(), y.floor());
+        let (tx, ty) = (x - x0, y - y0);
+        let (x0, y0) = (x0 as i64, y0 as i64);
+
+        self.texel(x0, y0, wrap) * ((1.0 - tx) * (1.0 - ty))
+            + self.texel(x0 + 1, y0, wrap) * (tx * (1.0 - ty))
+            + self.texel(x0, y0 + 1, wrap) * ((1.0 - tx) * ty)
+            + self.texel(x0 + 1, y0 + 1, wrap) * (tx * ty)
+    }
+
+    // A Gaussian weighted average of the texels in the ellipse with axes
+    // `axis0` and `axis1` around `uv`, all in texture space.
+    fn ewa(
+        &self,
+        uv: Vector2<f64>,
+        axis0: Vector2<f64>,
+        axis1: Vector2<f64>,
+        wrap: WrapMode,
+    ) -> Colour<f64> {
+        let (x, y) = self.position(uv);
+        let (x, y) = (x - 0.5, y - 0.5);
+        let (width, height) = (self.width as f64, self.height as f64);
+        let axis0 = Vector2::new(axis0.x * width, -axis0.y * height);
+        let axis1 = Vector2::new(axis1.x * width, -axis1.y * height);
+
+        // The implicit ellipse a x^2 + b xy + c y^2 = 1, widened by a texel
+        // so that it always covers some.
+        let a = axis0.y * axis0.y + axis1.y * axis1.y + 1.0;
+        let b = -2.0 * (axis0.x * axis0.y + axis1.x * axis1.y);
+        let c = axis0.x * axis0.x + axis1.x * axis1.x + 1.0;
+        let f = a * c - b * b / 4.0;
+        let (a, b, c) = (a / f, b / f, c / f);
+
+        // Past the size of the level the ellipse only wraps over the same
+        // texels again, so its bounds are capped there to bound the work.
+        let det = 4.0 * a * c - b * b;
+        let x_extent = (2.0 * (c / det).sqrt()).min(width);
+        let y_extent = (2.0 * (a / det).sqrt()).min(height);
+        let (x0, x1) = ((x - x_extent).ceil() as i64, (x + x_extent).floor() as i64);
+        let (y0, y1) = ((y - y_extent).ceil() as i64, (y + y_extent).floor() as i64);
+
+        let mut sum = Colour::new(0.0, 0.0, 0.0);
+        let mut total = 0.0;
+        for ty in y0..=y1 {
+            let dy = ty as f64 - y;
+            for tx in x0..=x1 {
+                let dx = tx as f64 - x;
+                let r2 = a * dx * dx + b * dx * dy + c * dy * dy;
+                if r2 < 1.0 {
+                    let weight = (-2.0 * r2).exp() - (-2.0f64).exp();
+                    sum = sum + self.texel(tx, ty, wrap) * weight;
+                    total += weight;
+                }
+            }
+        }
+
+        if total > 0.0 {
+            sum * (1.0 / total)
+        } else {
+            self.bilinear(uv, wrap)
+        }
+    }
+
+    // Half the size, each texel the mean of the ones it covers. Odd sizes
+    // round down, with the last texels folded into the last block.
+    fn downsample(&self) -> Level {
+        let width = (self.width / 2).max(1);
+        let height = (self.height / 2).max(1);
+        let span =
+            |i: u32, n: u32, from: u32| (i * from / n)..((i + 1) * from / n).max(i * from / n + 1);
+
+        let mut texels = Vec::with_capacity((width * height) as usize);
+        for y in 0..height {
+            for x in 0..width {
+                let mut sum = Colour::new(0.0, 0.0, 0.0);
+                let mut count = 0.0;
+                for sy in span(y, height, self.height) {
+                    for sx in span(x, width, self.width) {
+                        sum = sum + self.texels[(sy * self.width + sx) as usize];
+                        count += 1.0;
+                    }
+                }
+                texels.push(sum * (1.0 / count));
+            }
+        }
+
+        Level {
+            width,
+            height,
+            texels,
+        }
+    }
+}
+
+// An image with its MIP pyramid, from full size down to a single texel.
+pub struct ImageTexture {
+    levels: Vec<Level>,
+}
+
+// Texels are left out, as there can be millions of them.
+impl fmt::Debug for ImageTexture {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        let level = &self.levels[0];
+        f.debug_struct("ImageTexture")
+            .field("width", &level.width)
+            .field("height", &level.height)
+            .finish_non_exhaustive()
+    }
+}
+
+impl ImageTexture {
+    pub fn new(image: &Framebuffer) -> Self {
+        let texels = image
+            .pixels
+            .iter()
+            .map(|c| Colour::new(c.r as f32, c.g as f32, c.b as f32))
+            .collect();
+        let mut levels = vec![Level {
+            width: image.width,
+            height: image.height,
+            texels,
+        }];
+
+        while let Some(level) = levels.last().filter(|l| l.width > 1 || l.height > 1) {
+            levels.push(level.downsample());
+        }
+        ImageTexture { levels }
+    }
+
+    // Reads an image as `read_image` does.
+    pub fn open(path: &Path, srgb: bool) -> io::Result<Self> {
+        Ok(ImageTexture::new(&read_image(path, srgb)?))
+    }
+
+    // The level, with a fraction towards the next coarser one, on which a
+    // footprint `width` wide in texture space covers about a texel.
+    fn level(&self, width: f64) -> f64 {
+        let finest = &self.levels[0];
+        let texels = width * finest.width.max(finest.height) as f64;
+        texels.max(1.0).log2().min((self.levels.len() - 1) as f64)
+    }
+
+    // Blends `lookup` on the levels either side of `level`.
+    fn lerp_levels(&self, level: f64, lookup: impl Fn(&Level) -> Colour<f64>) -> Colour<f64> {
+        let lower = level.floor() as usize;
+        let t = level - lower as f64;
+        let colour = lookup(&self.levels[lower]);
+        if t > 0.0 {
+            colour * (1.0 - t) + lookup(&self.levels[lower + 1]) * t
+        } else {
+            colour
+        }
+    }
+
+    // The image at `uv`, filtered over the footprint that `duvdx` and `duvdy`
+    // span in texture space. Nearest and bilinear filtering ignore it.
+    pub fn sample(
+        &self,
+        uv: Vector2<f64>,
+        duvdx: Vector2<f64>,
+        duvdy: Vector2<f64>,
+        filter: ImageFilter,
+        wrap: WrapMode,
+    ) -> Colour<f64> {
+        match filter {
+            ImageFilter::Nearest => self.levels[0].nearest(uv, wrap),
+            ImageFilter::Bilinear => self.levels[0].bilinear(uv, wrap),
+            ImageFilter::Trilinear => {
+                let width = 2.0
+                    * duvdx
+                        .x
+                        .abs()
+                        .max(duvdx.y.abs())
+                        .max(duvdy.x.abs())
+                        .max(duvdy.y.abs());
+                self.lerp_levels(self.level(width), |level| level.bilinear(uv, wrap))
+            }
+            ImageFilter::Ewa => {
+                const MAX_ANISOTROPY: f64 = 8.0;
+
+                let (major, mut minor) = if duvdx.magnitude2() < duvdy.magnitude2() {
+                    (duvdy, duvdx)
+                } else {
+                    (duvdx, duvdy)
+                };
+                let major_length = major.magnitude();
+                let minor_length = minor.magnitude();
+                if minor_length == 0.0 {
+                    return self.levels[0].bilinear(uv, wrap);
+                }
+
+                // Very eccentric ellipses would cover too many texels, so
+                // they are fattened, trading some blur for bounded work.
+                let mut width = minor_length;
+                if minor_length * MAX_ANISOTROPY < major_length {
+                    let scale = major_length / (minor_length * MAX_ANISOTROPY);
+                    minor *= scale;
+                    width *= scale;
+                }
+
+                // A footprint wider than the image averages all of it, which
+                // the coarsest level already holds.
+                let level = self.level(width);
+                let coarsest = self.levels.len() - 1;
+                if level >= coarsest as f64 {
+                    return self.levels[coarsest].texel(0, 0, wrap);
+                }
+                self.lerp_levels(level, |level| level.ewa(uv, major, minor, wrap))
+            }
+        }
+    }
+}
+
+// The images loaded so far, so that textures sharing an image share its
+// memory.
+#[derive(Default)]
+pub struct ImageCache {
+    images: RefCell<HashMap<(PathBuf, bool), Arc<ImageTexture>>>,
+}
+
+impl ImageCache {
+    pub fn open(&self, path: &Path, srgb: bool) -> io::Result<Arc<ImageTexture>> {
+        let key = (path.to_path_buf(), srgb);
+        if let Some(image) = self.images.borrow().get(&key) {
+            return Ok(image.clone());
+        }
+
+        let image = Arc::new(ImageTexture::open(path, srgb)?);
+        self.images.borrow_mut().insert(key, image.clone());
+        Ok(image)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // A checkerboard of black and white texels, 256 across.
+    fn checkerboard() -> ImageTexture {
+        let size = 256;
+        let pixels = (0..size * size)
+            .map(|i| {
+                let value = ((i % size + i / size) % 2) as f64;
+                Colour::new(value, value, value)
+            })
+            .collect();
+        ImageTexture::new(&Framebuffer {
+            width: size,
+            height: size,
+            pixels,
+        })
+    }
+
+    #[test]
+    fn huge_ewa_footprints_average_the_whole_image() {
+        let texture = checkerboard();
+        for (duvdx, duvdy) in [
+            (Vector2::new(1e6, 0.0), Vector2::new(0.0, 1e6)),
+            (Vector2::new(1e6, 1e6), Vector2::new(-1e5, 1e5)),
+        ] {
+            for wrap in [WrapMode::Repeat, WrapMode::Clamp, WrapMode::Mirror] {
+                let colour =
+                    texture.sample(Vector2::new(0.3, 0.7), duvdx, duvdy, ImageFilter::Ewa, wrap);
+                for channel in [colour.r, colour.g, colour.b] {
+                    assert!((channel - 0.5).abs() < 1e-6, "{channel}");
+                }
+            }
+        }
+    }
+
+    // Footprints a little smaller than the image stop short of the coarsest
+    // level, where the capped ellipse still averages the whole image.
+    #[test]
+    fn image_wide_ewa_footprints_stay_near_the_mean() {
+        let texture = checkerboard();
+        let colour = texture.sample(
+            Vector2::new(0.3, 0.7),
+            Vector2::new(0.9, 0.0),
+            Vector2::new(0.0, 0.9),
+            ImageFilter::Ewa,
+            WrapMode::Repeat,
+        );
+        assert!((colour.r - 0.5).abs() < 0.05, "{}", colour.r);
+    }
+}
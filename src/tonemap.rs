@@ -0,0 +1,288 @@
+use serde::{Deserialize, Serialize};
+
+use crate::colour::Colour;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(rename_all = "snake_case")]
+pub enum ToneMapper {
+    Clamp,
+    Reinhard,
+    Hable,
+    Aces,
+    Agx,
+}
+
+// Turns linear radiance into display values for the 8 and 16-bit formats:
+// scales it by `exposure` stops, adapts the white of a light of
+// `white_balance` kelvin to D65 so that it looks neutral, compresses it into
+// the displayable range with `tone_mapper` and encodes it with the sRGB
+// transfer function.
+#[derive(Debug, Clone, Copy, PartialEq, Serialize, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct DisplayTransform {
+    pub exposure: f64,
+    pub white_balance: Option<f64>,
+    pub tone_mapper: ToneMapper,
+}
+
+impl Default for DisplayTransform {
+    fn default() -> Self {
+        DisplayTransform {
+            exposure: 0.0,
+            white_balance: None,
+            tone_mapper: ToneMapper::Clamp,
+        }
+    }
+}
+
+type Matrix = [[f64; 3]; 3];
+
+fn multiply(m: &Matrix, c: Colour<f64>) -> Colour<f64> {
+    let row = |r: [f64; 3]| r[0] * c.r + r[1] * c.g + r[2] * c.b;
+    Colour::new(row(m[0]), row(m[1]), row(m[2]))
+}
+
+fn map(c: Colour<f64>, f: impl Fn(f64) -> f64) -> Colour<f64> {
+    Colour::new(f(c.r), f(c.g), f(c.b))
+}
+
+const XYZ_FROM_SRGB: Matrix = [
+    [0.4124564, 0.3575761, 0.1804375],
+    [0.2126729, 0.7151522, 0.0721750],
+    [0.0193339, 0.1191920, 0.9503041],
+];
+
+const SRGB_FROM_XYZ: Matrix = [
+    [3.2404542, -1.5371385, -0.4985314],
+    [-0.9692660, 1.8760108, 0.0415560],
+    [0.0556434, -0.2040259, 1.0572252],
+];
+
+const BRADFORD: Matrix = [
+    [0.8951, 0.2664, -0.1614],
+    [-0.7502, 1.7135, 0.0367],
+    [0.0389, -0.0685, 1.0296],
+];
+
+const BRADFORD_INVERSE: Matrix = [
+    [0.9869929, -0.1470543, 0.1599627],
+    [0.4323053, 0.5183603, 0.0492912],
+    [-0.0085287, 0.0400428, 0.9684867],
+];
+
+// The chromaticity of the white of a light at `kelvin`: on the CIE daylight
+// locus from 4000 K, so that 6504 K gives D65, and below that on the black
+// body locus, from the cubic fit of Kim et al.
+fn white_xy(kelvin: f64) -> (f64, f64) {
+    let t = kelvin.clamp(1667.0, 25000.0);
+    let (t2, t3) = (t * t, t * t * t);
+
+    if t >= 4000.0 {
+        let x = if t <= 7000.0 {
+            -4.6070e9 / t3 + 2.9678e6 / t2 + 0.09911e3 / t + 0.244063
+        } else {
+            -2.0064e9 / t3 + 1.9018e6 / t2 + 0.24748e3 / t + 0.237040
+        };
+        return (x, -3.0 * x * x + 2.870 * x - 0.275);
+    }
+
+    let x = -0.2661239e9 / t3 - 0.2343589e6 / t2 + 0.8776956e3 / t + 0.179910;
+    let (x2, x3) = (x * x, x * x * x);
+    let y = if t <= 2222.0 {
+        -1.1063814 * x3 - 1.34811020 * x2 + 2.18555832 * x - 0.20219683
+    } else {
+        -0.9549476 * x3 - 1.37418593 * x2 + 2.09137015 * x - 0.16748867
+    };
+    (x, y)
+}
+
+// Bradford chromatic adaptation in linear sRGB from the white of a light at
+// `kelvin` to D65.
+fn white_balance(kelvin: f64) -> Matrix {
+    let cone = |(x, y): (f64, f64)| {
+        let xyz = Colour::new(x / y, 1.0, (1.0 - x - y) / y);
+        multiply(&BRADFORD, xyz)
+    };
+    let source = cone(white_xy(kelvin));
+    let target = cone((0.31271, 0.32902));
+    let scale = [
+        target.r / source.r,
+        target.g / source.g,
+        target.b / source.b,
+    ];
+
+    let column = |k: usize| {
+        let c = Colour::new(
+            XYZ_FROM_SRGB[0][k],
+            XYZ_FROM_SRGB[1][k],
+            XYZ_FROM_SRGB[2][k],
+        );
+        let lms = multiply(&BRADFORD, c);
+        let lms = Colour::new(lms.r * scale[0], lms.g * scale[1], lms.b * scale[2]);
+        multiply(&SRGB_FROM_XYZ, multiply(&BRADFORD_INVERSE, lms))
+    };
+    let (r, g, b) = (column(0), column(1), column(2));
+    [[r.r, g.r, b.r], [r.g, g.g, b.g], [r.b, g.b, b.b]]
+}
+
+// John Hable's filmic curve from Uncharted 2, with its white point at 11.2.
+fn hable(x: f64) -> f64 {
+    let curve = |x: f64| {
+        let (a, b, c, d, e, f) = (0.15, 0.50, 0.10, 0.20, 0.02, 0.30);
+        (x * (a * x + c * b) + d * e) / (x * (a * x + b) + d * f) - e / f
+    };
+    curve(2.0 * x) / curve(11.2)
+}
+
+// Stephen Hill's fit of the ACES reference rendering and sRGB output
+// transforms.
+fn aces(c: Colour<f64>) -> Colour<f64> {
+    const INPUT: Matrix = [
+        [0.59719, 0.35458, 0.04823],
+        [0.07600, 0.90834, 0.01566],
+        [0.02840, 0.13383, 0.83777],
+    ];
+    const OUTPUT: Matrix = [
+        [1.60475, -0.53108, -0.07367],
+        [-0.10208, 1.10813, -0.00605],
+        [-0.00327, -0.07276, 1.07602],
+    ];
+
+    let fit =
+        |v: f64| (v * (v + 0.0245786) - 0.000090537) / (v * (0.983729 * v + 0.432951) + 0.238081);
+    multiply(&OUTPUT, map(multiply(&INPUT, c), fit))
+}
+
+// Troy Sobotka's AgX with the default look, after Benjamin Wrensch's
+// polynomial approximation of its sigmoid.
+fn agx(c: Colour<f64>) -> Colour<f64> {
+    const INSET: Matrix = [
+        [0.842479062253094, 0.0784335999999992, 0.0792237451477643],
+        [0.0423282422610123, 0.878468636469772, 0.0791661274605434],
+        [0.0423756549057051, 0.0784336, 0.879142973793104],
+    ];
+    const OUTSET: Matrix = [
+        [1.19687900512017, -0.0980208811401368, -0.0990297440797205],
+        [-0.0528968517574562, 1.15190312990417, -0.0989611768448433],
+        [-0.0529716355144438, -0.0980434501171241, 1.15107367264116],
+    ];
+    const MIN_EV: f64 = -12.47393;
+    const MAX_EV: f64 = 4.026069;
+
+    let sigmoid = |v: f64| {
+        let x = (v.max(1e-10).log2().clamp(MIN_EV, MAX_EV) - MIN_EV) / (MAX_EV - MIN_EV);
+        let (x2, x4) = (x * x, x * x * x * x);
+        15.5 * x4 * x2 - 40.14 * x4 * x + 31.96 * x4 - 6.868 * x2 * x + 0.4298 * x2 + 0.1191 * x
+            - 0.00232
+    };
+    // The sigmoid yields values encoded with a gamma of 2.2.
+    map(multiply(&OUTSET, map(multiply(&INSET, c), sigmoid)), |v| {
+        v.max(0.0).powf(2.2)
+    })
+}
+
+fn srgb_oetf(v: f64) -> f64 {
+    if v <= 0.0031308 {
+        12.92 * v
+    } else {
+        1.055 * v.powf(1.0 / 2.4) - 0.055
+    }
+}
+
+impl DisplayTransform {
+    // Maps linear colours to display values with each channel in [0, 1]. The
+    // exposure scale and white balance matrix are worked out once here, so
+    // build this once per image rather than for every pixel.
+    pub fn pixel_map(&self) -> impl Fn(Colour<f64>) -> Colour<f64> {
+        let scale = 2f64.powf(self.exposure);
+        let white_balance = self.white_balance.map(white_balance);
+        let tone_mapper = self.tone_mapper;
+
+        move |colour| {
+            let mut c = colour * scale;
+            if let Some(white_balance) = &white_balance {
+                c = multiply(white_balance, c);
+            }
+            let c = map(c, |v| v.max(0.0));
+
+            let mapped = match tone_mapper {
+                ToneMapper::Clamp => c,
+                ToneMapper::Reinhard => map(c, |v| v / (1.0 + v)),
+                ToneMapper::Hable => map(c, hable),
+                ToneMapper::Aces => aces(c),
+                ToneMapper::Agx => agx(c),
+            };
+            map(mapped, |v| srgb_oetf(v.clamp(0.0, 1.0)))
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const TONE_MAPPERS: [ToneMapper; 5] = [
+        ToneMapper::Clamp,
+        ToneMapper::Reinhard,
+        ToneMapper::Hable,
+        ToneMapper::Aces,
+        ToneMapper::Agx,
+    ];
+
+    #[test]
+    fn daylight_white_balance_is_the_identity() {
+        let matrix = white_balance(6504.0);
+        for (i, row) in matrix.iter().enumerate() {
+            for (j, &value) in row.iter().enumerate() {
+                let identity = if i == j { 1.0 } else { 0.0 };
+                assert!((value - identity).abs() < 1e-3, "{matrix:?}");
+            }
+        }
+    }
+
+    // Greys from black up to far past white, and colours well outside the
+    // sRGB gamut.
+    #[test]
+    fn tone_mappers_are_monotonic_and_displayable() {
+        for tone_mapper in TONE_MAPPERS {
+            let pixel_map = DisplayTransform {
+                tone_mapper,
+                ..DisplayTransform::default()
+            }
+            .pixel_map();
+
+            let black = pixel_map(Colour::new(0.0, 0.0, 0.0));
+            assert_eq!(
+                (black.r, black.g, black.b),
+                (0.0, 0.0, 0.0),
+                "{tone_mapper:?}"
+            );
+
+            let mut previous = black;
+            for i in 1..=400 {
+                let v = 2f64.powf(i as f64 / 20.0 - 12.0);
+                let c = pixel_map(Colour::new(v, v, v));
+                for (before, after) in [(previous.r, c.r), (previous.g, c.g), (previous.b, c.b)] {
+                    assert!(after >= before, "{tone_mapper:?} falls at {v}");
+                    assert!(
+                        (0.0..=1.0).contains(&after),
+                        "{tone_mapper:?} gives {after}"
+                    );
+                }
+                previous = c;
+            }
+
+            for colour in [
+                Colour::new(100.0, 0.0, 0.0),
+                Colour::new(0.0, 5.0, 0.0),
+                Colour::new(0.0, 0.0, 1e4),
+                Colour::new(-1.0, 0.5, 2.0),
+            ] {
+                let c = pixel_map(colour);
+                for v in [c.r, c.g, c.b] {
+                    assert!((0.0..=1.0).contains(&v), "{tone_mapper:?} gives {v}");
+                }
+            }
+        }
+    }
+}
@@ -1,38 +1,27 @@
-use cgmath::{InnerSpace, Vector3};
-use rand::{distributions::uniform::SampleUniform, prelude::Distribution};
+use std::f64::consts::PI;
 
-pub fn random_vector3<T: SampleUniform>(min: T, max: T) -> Vector3<T> {
-    let between = rand::distributions::Uniform::new(min, max);
-    let mut rng = rand::thread_rng();
+use cgmath::Vector3;
 
-    Vector3 {
-        x: between.sample(&mut rng),
-        y: between.sample(&mut rng),
-        z: between.sample(&mut rng),
+// Maps a point of the unit square onto the unit disk with Shirley and Chiu's
+// concentric mapping, which keeps strata of the square compact on the disk.
+pub fn sample_unit_disk((u, v): (f64, f64)) -> Vector3<f64> {
+    let (x, y) = (2.0 * u - 1.0, 2.0 * v - 1.0);
+    if x == 0.0 && y == 0.0 {
+        return Vector3::new(0.0, 0.0, 0.0);
     }
-}
-
-pub fn random_in_unit_sphere() -> Vector3<f64> {
-    loop {
-        let p: Vector3<f64> = random_vector3(-1.0, 1.0);
-        if p.magnitude2() < 1.0 {
-            return p;
-        }
-    }
-}
 
-pub fn random_on_unit_sphere() -> Vector3<f64> {
-    random_in_unit_sphere().normalize()
+    let (r, theta) = if x.abs() > y.abs() {
+        (x, PI / 4.0 * (y / x))
+    } else {
+        (y, PI / 2.0 - PI / 4.0 * (x / y))
+    };
+    Vector3::new(r * theta.cos(), r * theta.sin(), 0.0)
 }
 
-pub fn random_in_unit_disk() -> Vector3<f64> {
-    let between = rand::distributions::Uniform::new(-1.0, 1.0);
-    let mut rng = rand::thread_rng();
-
-    loop {
-        let p = Vector3::new(between.sample(&mut rng), between.sample(&mut rng), 0.0);
-        if p.magnitude2() < 1.0 {
-            return p;
-        }
-    }
+// Maps a point of the unit square uniformly onto the unit sphere.
+pub fn sample_unit_sphere((u, v): (f64, f64)) -> Vector3<f64> {
+    let z = 1.0 - 2.0 * u;
+    let r = (1.0 - z * z).max(0.0).sqrt();
+    let phi = 2.0 * PI * v;
+    Vector3::new(r * phi.cos(), r * phi.sin(), z)
 }
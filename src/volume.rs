@@ -0,0 +1,97 @@
+use std::sync::Arc;
+
+use cgmath::{InnerSpace, Vector2, Vector3};
+
+use crate::{
+    aabb::Aabb,
+    colour::Colour,
+    ray::Ray,
+    render::splitmix64,
+    shapes::{HitRecord, Hittable, Material},
+};
+
+pub struct ConstantMedium {
+    boundary: Arc<dyn Hittable>,
+    neg_inv_density: f64,
+    phase_function: Material,
+    seed: u64,
+}
+
+impl ConstantMedium {
+    // `seed` is the render seed, so that other seeds scatter differently.
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: Colour<f64>, seed: u64) -> Self {
+        ConstantMedium {
+            boundary,
+            neg_inv_density: -1.0 / density,
+            phase_function: Material::Isotropic {
+                albedo: albedo.into(),
+            },
+            seed,
+        }
+    }
+}
+
+// Hittable::hit has no random number generator to draw from, so the free-flight
+// distance is sampled from a hash of the ray itself and the render seed. Every
+// sample traces its own rays, so this keeps renders deterministic for a given
+// seed however they are split across threads.
+fn ray_hash(ray: &Ray, seed: u64) -> f64 {
+    let bits = [
+        ray.origin.x,
+        ray.origin.y,
+        ray.origin.z,
+        ray.direction.x,
+        ray.direction.y,
+        ray.direction.z,
+    ];
+    let hash = bits.iter().fold(splitmix64(seed), |hash, v| {
+        let mut x = hash ^ v.to_bits();
+        x = (x ^ (x >> 33)).wrapping_mul(0xff51_afd7_ed55_8ccd);
+        x = (x ^ (x >> 33)).wrapping_mul(0xc4ce_b9fe_1a85_ec53);
+        x ^ (x >> 33)
+    });
+
+    // 53 random mantissa bits in (0, 1], never zero so the log stays finite.
+    ((hash >> 11) + 1) as f64 / (1u64 << 53) as f64
+}
+
+impl Hittable for ConstantMedium {
+    fn hit(&self, ray: &Ray, t_min: f64, t_max: f64) -> Option<HitRecord<'_>> {
+        let entry = self.boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY)?;
+        let exit = self.boundary.hit(ray, entry.t + 0.0001, f64::INFINITY)?;
+
+        let t_enter = entry.t.max(t_min).max(0.0);
+        let t_exit = exit.t.min(t_max);
+        if t_enter >= t_exit {
+            return None;
+        }
+
+        let ray_length = ray.direction.magnitude();
+        let distance_inside = (t_exit - t_enter) * ray_length;
+        let hit_distance = self.neg_inv_density * ray_hash(ray, self.seed).ln();
+        if hit_distance > distance_inside {
+            return None;
+        }
+
+        let t = t_enter + hit_distance / ray_length;
+
+        // The normal is arbitrary inside a volume; the isotropic phase
+        // function ignores it.
+        Some(HitRecord {
+            p: ray.at(t),
+            normal: Vector3::unit_x(),
+            t,
+            front_face: true,
+            uv: Vector2::new(0.0, 0.0),
+            footprint: None,
+            material: &self.phase_function,
+            object: 0,
+            primitive: 0,
+            material_index: 0,
+        })
+    }
+
+    fn bounding_box(&self) -> Option<Aabb> {
+        self.boundary.bounding_box()
+    }
+}
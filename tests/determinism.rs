@@ -0,0 +1,69 @@
+use std::{fs, path::Path, process::Command};
+
+// A small lit scene with a volume, whose scattering is decided inside
+// `Hittable::hit` rather than by the sampler.
+const SCENE: &str = r#"
+[camera]
+look_from = [0, 1, 5]
+look_at = [0, 0.5, 0]
+vertical_fov = 40
+
+[render]
+width = 24
+height = 16
+samples_per_pixel = 8
+seed = 7
+
+[materials.ground]
+type = "lambertian"
+albedo = [0.5, 0.5, 0.5]
+
+[materials.light]
+type = "diffuse_light"
+emit = [4, 4, 4]
+
+[[shapes]]
+type = "plane"
+point = [0, 0, 0]
+normal = [0, 1, 0]
+material = "ground"
+
+[[shapes]]
+type = "quad"
+corner = [-1, 3, -1]
+u = [2, 0, 0]
+v = [0, 0, 2]
+material = "light"
+
+[[shapes]]
+type = "volume"
+density = 0.8
+albedo = [0.8, 0.6, 0.4]
+boundary = { type = "sphere", center = [0, 1, 0], radius = 1, material = "ground" }
+"#;
+
+fn render(dir: &Path, threads: usize) -> Vec<u8> {
+    let output = dir.join(format!("j{threads}.pfm"));
+    let result = Command::new(env!("CARGO_BIN_EXE_rust-tracer"))
+        .args(["render", "-s"])
+        .arg(dir.join("scene.toml"))
+        .args(["-j", &threads.to_string(), "-o"])
+        .arg(&output)
+        .output()
+        .expect("could not run the renderer");
+    assert!(result.status.success(), "{result:?}");
+    fs::read(output).unwrap()
+}
+
+#[test]
+fn renders_do_not_depend_on_thread_count() {
+    let dir = std::env::temp_dir().join(format!("rust-tracer-determinism-{}", std::process::id()));
+    fs::create_dir_all(&dir).unwrap();
+    fs::write(dir.join("scene.toml"), SCENE).unwrap();
+
+    let single = render(&dir, 1);
+    let parallel = render(&dir, 4);
+    fs::remove_dir_all(&dir).unwrap();
+
+    assert!(single == parallel, "-j 1 and -j 4 renders differ");
+}